@@ -1,9 +1,22 @@
-use anchor_lang::{prelude::*, solana_program::clock};
+use anchor_lang::{prelude::*, solana_program::{clock, hash}, system_program, AccountsClose, Discriminator};
 use anchor_spl::token::{CloseAccount, Mint, Token, TokenAccount, Transfer};
 
 declare_id!("ERCw53LxmDDUZsjay7nRyPJJcHkEWEvxxLZ8bmeYWJDf");
 
 const DAY_IN_SECONDS: u64 = 60 * 60 * 24;
+const MAX_BASIS_POINTS: u64 = 10_000;
+const BASIS_POINTS_PER_PERCENT: u64 = 100;
+// Scale of Config.reward_per_token, keeps small rewards over large stakes from rounding to 0
+const REWARD_PRECISION: u128 = 1_000_000_000_000;
+const MAX_UNSTAKE_COOLDOWN_IN_SECONDS: u64 = 30 * DAY_IN_SECONDS;
+const MAX_MODERATION_SLASH_PERCENTAGE: u8 = 50;
+// Moderation closes this long after a campaign starts, campaigns without a deadline may never conclude
+const MODERATION_PERIOD_IN_SECONDS: i64 = 30 * DAY_IN_SECONDS as i64;
+// Staking weight boosts, added on top of the MAX_BASIS_POINTS an unboosted deposit weighs
+const MAX_STAKE_LOCK_IN_SECONDS: u64 = 365 * DAY_IN_SECONDS;
+const MAX_LOCK_BOOST_BASIS_POINTS: u64 = 10_000;
+const STAKE_AGE_FOR_FULL_BOOST_IN_SECONDS: u64 = 180 * DAY_IN_SECONDS;
+const MAX_AGE_BOOST_BASIS_POINTS: u64 = 5_000;
 
 #[program]
 pub mod pandora {
@@ -14,6 +27,7 @@ pub mod pandora {
         let config = &mut ctx.accounts.config;
         
         config.admin = ctx.accounts.authority.key();
+        config.pending_admin = Pubkey::default();
         config.native_token_mint = ctx.accounts.native_token_mint.key();
         config.donation_fee = 0;
         config.staking_initialized = false;
@@ -21,15 +35,78 @@ pub mod pandora {
         config.total_amount_staked = 0;
         config.round_voting_period_in_days = 1;
         config.minimum_required_vote_percentage = 30;
+        config.minimum_moderation_vote_percentage = 30;
         config.donator_voting_rights = 60;
         config.staker_voting_rights = 40;
         config.staker_moderation_rights = 100;
+        config.unstake_cooldown_in_seconds = 7 * DAY_IN_SECONDS;
+        config.moderation_slash_percentage = 0;
         config.staking_pool = Pubkey::default();
+        config.reward_per_token = 0;
         config.bump = *ctx.bumps.get("config").unwrap();
 
         Ok(())
     }
 
+    pub fn update_config(ctx: Context<UpdateConfig>, params: GovernanceParams) -> Result<()> {
+        params.validate()?;
+
+        let config = &mut ctx.accounts.config;
+        let old_params = config.governance_params();
+        config.set_governance_params(&params);
+
+        emit!(ConfigUpdated {
+            admin: ctx.accounts.admin.key(),
+            old: old_params,
+            new: params,
+        });
+
+        Ok(())
+    }
+
+    // Proposing the default pubkey cancels a pending transfer
+    pub fn propose_admin(ctx: Context<ProposeAdmin>, new_admin: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.pending_admin = new_admin;
+
+        emit!(AdminProposed {
+            admin: config.admin,
+            pending_admin: new_admin,
+        });
+
+        Ok(())
+    }
+
+    pub fn accept_admin(ctx: Context<AcceptAdmin>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let old_admin = config.admin;
+        config.admin = ctx.accounts.pending_admin.key();
+        config.pending_admin = Pubkey::default();
+
+        emit!(AdminTransferred {
+            old_admin,
+            new_admin: config.admin,
+        });
+
+        Ok(())
+    }
+
+    // Irreversible: every admin-gated endpoint becomes unreachable
+    pub fn renounce_admin(ctx: Context<RenounceAdmin>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let old_admin = config.admin;
+        config.admin = Pubkey::default();
+        config.pending_admin = Pubkey::default();
+
+        emit!(AdminTransferred {
+            old_admin,
+            new_admin: Pubkey::default(),
+        });
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn start_campaign(
         ctx: Context<StartCampaign>,
         description: String,
@@ -37,7 +114,13 @@ pub mod pandora {
         number_of_funding_rounds: u8,
         initial_target: u64,
         cid: String,
+        deadline: Option<i64>,
+        funding_mode: FundingMode,
+        commit_reveal: bool,
+        quadratic_voting: bool,
     ) -> Result<()> {
+        let current_time = clock::Clock::get().unwrap().unix_timestamp;
+        let round_deadline = validate_deadline(deadline, current_time)?;
         require!(target > 0, ErrorCode::InvalidTarget);
         require!(
             description.chars().count() <= MAX_DESCRIPTION_SIZE,
@@ -56,8 +139,14 @@ pub mod pandora {
             initial_round_target = initial_target;
         }
 
+        let creator_profile = &mut ctx.accounts.creator_profile;
+        let campaign_index = creator_profile.campaign_count;
+        creator_profile.campaign_count = campaign_index.checked_add(1).unwrap();
+        creator_profile.bump = *ctx.bumps.get("creator_profile").unwrap();
+
         let campaign = &mut ctx.accounts.campaign;
         campaign.fundstarter = ctx.accounts.fundstarter.key();
+        campaign.index = campaign_index;
         campaign.vault = ctx.accounts.vault.key();
         campaign.description = description;
         campaign.target = target;
@@ -69,10 +158,15 @@ pub mod pandora {
         campaign.total_rounds = number_of_funding_rounds;
         campaign.active_round = 1;
         campaign.active_round_address = ctx.accounts.round.key();
+        campaign.start_time = current_time;
+        campaign.moderation_end_time = current_time.checked_add(MODERATION_PERIOD_IN_SECONDS).unwrap();
         campaign.is_valid_votes = 0;
         campaign.not_valid_votes = 0;
         campaign.moderator_votes = 0;
         campaign.is_valid_campaign = true;
+        campaign.funding_mode = funding_mode.to_u8();
+        campaign.commit_reveal = commit_reveal;
+        campaign.quadratic_voting = quadratic_voting;
         campaign.bump = *ctx.bumps.get("campaign").unwrap();
 
         let round = &mut ctx.accounts.round;
@@ -82,6 +176,10 @@ pub mod pandora {
         round.balance = 0;
         round.donators = 0;
         round.status = RoundStatus::DonationsOpen.to_u8();
+        round.withdrawn = 0;
+        round.start_time = current_time;
+        round.deadline = round_deadline;
+        round.sqrt_donations = 0;
 
         Ok(())
     }
@@ -97,15 +195,36 @@ pub mod pandora {
             round_status == RoundStatus::DonationsOpen,
             ErrorCode::RoundClosedToDonations
         );
+        let current_time = clock::Clock::get().unwrap().unix_timestamp;
+        require!(
+            !ctx.accounts.round.deadline_passed(current_time),
+            ErrorCode::RoundDeadlinePassed
+        );
 
+        let fee = ctx.accounts.config.donation_fee_for(amount);
         let round = &mut ctx.accounts.round;
         let donator_account = &mut ctx.accounts.donator_account;
         let campaign = &mut ctx.accounts.campaign;
         let donating_wallet = ctx.accounts.donator_token_account.to_owned();
         let vault = &mut ctx.accounts.vault.to_owned();
+        let treasury = ctx.accounts.treasury.to_owned();
         let donator = ctx.accounts.donator.to_owned();
         let token_program = ctx.accounts.token_program.to_owned();
-        let donation_size = amount;
+        // Only the amount left after the protocol fee counts towards the campaign
+        let donation_size = amount.checked_sub(fee).unwrap();
+        require!(donation_size > 0, ErrorCode::InvalidDonationAmount);
+        // A fresh account is zeroed, so a donator we haven't seen this round has no donations yet
+        let first_donation = donator_account.donation_count == 0;
+
+        if fee > 0 {
+            let fee_instruction = Transfer {
+                from: donating_wallet.to_account_info(),
+                to: treasury.to_account_info(),
+                authority: donator.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new(token_program.to_account_info(), fee_instruction);
+            anchor_spl::token::transfer(cpi_ctx, fee)?;
+        }
 
         let transfer_instruction = Transfer {
             from: donating_wallet.to_account_info(),
@@ -119,12 +238,17 @@ pub mod pandora {
         campaign.balance = campaign.balance.checked_add(donation_size).unwrap();
 
         round.balance = round.balance.checked_add(donation_size).unwrap();
-        round.donators = round.donators.checked_add(1).unwrap();
 
-        //donator_account.donator = ctx.accounts.donator.key();
-        donator_account.amount = amount;
-        donator_account.round = campaign.active_round;
-        donator_account.bump = *ctx.bumps.get("donator_account").unwrap();
+        if first_donation {
+            round.donators = round.donators.checked_add(1).unwrap();
+            //donator_account.donator = ctx.accounts.donator.key();
+            donator_account.round = campaign.active_round;
+            donator_account.bump = *ctx.bumps.get("donator_account").unwrap();
+        }
+        let previous_amount = donator_account.amount;
+        donator_account.amount = donator_account.amount.checked_add(donation_size).unwrap();
+        round.update_sqrt_donations(previous_amount, donator_account.amount);
+        donator_account.donation_count = donator_account.donation_count.checked_add(1).unwrap();
 
         //vault.reload()?;
         if round.balance >= round.target {
@@ -148,7 +272,20 @@ pub mod pandora {
         round_votes.donators_voted = 0;
         round_votes.stakers_voted = 0;
         round_votes.start_time = clock.unix_timestamp;
+        round_votes.end_time = clock.unix_timestamp
+            .checked_add(ctx.accounts.config.round_voting_period())
+            .unwrap();
+        // Committed ballots get another voting period to be revealed in
+        round_votes.reveal_end_time = if ctx.accounts.campaign.commit_reveal {
+            round_votes.end_time
+                .checked_add(ctx.accounts.config.round_voting_period())
+                .unwrap()
+        } else {
+            round_votes.end_time
+        };
         round_votes.voting_ended = false;
+        round_votes.total_amount_staked = ctx.accounts.config.total_amount_staked;
+        round_votes.active_stakers = ctx.accounts.config.active_stakers;
 
         let round = &mut ctx.accounts.round;
         round.round_votes = ctx.accounts.round_votes.key();
@@ -164,84 +301,87 @@ pub mod pandora {
         );
         require_eq!(round_status, RoundStatus::RoundTargetMet.to_u8());
 
+        require!(!ctx.accounts.campaign.commit_reveal, ErrorCode::WrongVotingMode);
+
+        let current_time = clock::Clock::get().unwrap().unix_timestamp;
         let round_votes = &mut ctx.accounts.round_votes;
-        let voter = &mut ctx.accounts.voter_account;
+        require!(round_votes.is_open(current_time), ErrorCode::OutsideVotingWindow);
 
-        match continue_campaign {
-            true => {
-                round_votes.continue_campaign =
-                    round_votes.continue_campaign
-                    .checked_add(voter.voting_power.into())
-                    .unwrap();
-            }
-            false => {
-                round_votes.terminate_campaign =
-                    round_votes.terminate_campaign
-                    .checked_add(voter.voting_power.into())
-                    .unwrap();
-            }
-        }
+        round_votes.cast(&mut ctx.accounts.voter_account, continue_campaign)
+    }
 
-        match VoterType::from(voter.voter_type)? {
-            VoterType::Donator => {
-                round_votes.donators_voted = 
-                    round_votes.donators_voted.checked_add(1).unwrap();
-            },
-            VoterType::Staker => {
-                round_votes.stakers_voted = 
-                    round_votes.stakers_voted.checked_add(1).unwrap();
-            }
-        }
+    // Commit-reveal campaigns take a hash of the ballot while voting is open,
+    // committing again replaces the previous commitment
+    pub fn commit_vote(ctx: Context<VoteNextRound>, commitment: [u8; 32]) -> Result<()> {
+        let campaign_status = ctx.accounts.campaign.status;
+        let round_status = ctx.accounts.round.status;
+        require!(
+            campaign_status == CampaignStatus::CampaignActive.to_u8(),
+            ErrorCode::CampaignInactive
+        );
+        require_eq!(round_status, RoundStatus::RoundTargetMet.to_u8());
+        require!(ctx.accounts.campaign.commit_reveal, ErrorCode::WrongVotingMode);
 
-        voter.has_voted = true;
+        let current_time = clock::Clock::get().unwrap().unix_timestamp;
+        require!(ctx.accounts.round_votes.is_open(current_time), ErrorCode::OutsideVotingWindow);
+
+        ctx.accounts.voter_account.commitment = Some(commitment);
 
         Ok(())
     }
 
+    // Only revealed ballots are tallied
+    pub fn reveal_vote(ctx: Context<VoteNextRound>, continue_campaign: bool, salt: [u8; 32]) -> Result<()> {
+        let campaign_status = ctx.accounts.campaign.status;
+        let round_status = ctx.accounts.round.status;
+        require!(
+            campaign_status == CampaignStatus::CampaignActive.to_u8(),
+            ErrorCode::CampaignInactive
+        );
+        require_eq!(round_status, RoundStatus::RoundTargetMet.to_u8());
+        require!(ctx.accounts.campaign.commit_reveal, ErrorCode::WrongVotingMode);
+
+        let current_time = clock::Clock::get().unwrap().unix_timestamp;
+        let voter_key = ctx.accounts.voter.key();
+        let round_votes_key = ctx.accounts.round_votes.key();
+        let round_votes = &mut ctx.accounts.round_votes;
+        require!(round_votes.is_revealing(current_time), ErrorCode::OutsideRevealWindow);
+
+        let voter = &mut ctx.accounts.voter_account;
+        voter.reveal(&voter_key, &round_votes_key, continue_campaign, &salt)?;
+        round_votes.cast(voter, continue_campaign)
+    }
+
     // Should be chained in the same tx as the instruction to start next round
     pub fn tally_votes(ctx: Context<TallyVotes>) -> Result<()> {
         let current_time = clock::Clock::get().unwrap().unix_timestamp;
-        let voting_start_time = ctx.accounts.round_votes.start_time;
-        let time_elapsed_in_seconds = current_time - voting_start_time;
-        let voting_period_in_seconds = (ctx.accounts.config.round_voting_period_in_days as u64)
-            .checked_mul(DAY_IN_SECONDS)
-            .unwrap();
-
         require!(
-            time_elapsed_in_seconds as u64 > voting_period_in_seconds,
+            current_time > ctx.accounts.round_votes.reveal_end_time,
             ErrorCode::VotingStillActive
         );
 
         let round_votes = &mut ctx.accounts.round_votes;
-        let maximum_possible_voters = ctx.accounts.config.active_stakers
-            .checked_add(ctx.accounts.round.donators as u64)
-            .unwrap();
-        let voters_this_round = round_votes.stakers_voted
-            .checked_add(round_votes.donators_voted)
-            .unwrap();
-        let minimum_voters_required = (30 as u64)
-            .checked_mul(maximum_possible_voters)
-            .unwrap()
-            .checked_div(100 as u64)
-            .unwrap();
-
-        let round = &mut ctx.accounts.round;
-        let campaign = &mut ctx.accounts.campaign;
-
-        if round_votes.terminate_campaign > round_votes.continue_campaign &&
-             voters_this_round > minimum_voters_required 
-        {
-            campaign.can_start_next_round = false;
-        } 
-        
-        round.status = RoundStatus::RoundEnded.to_u8();
+        let terminated = round_votes.terminates_campaign(
+            ctx.accounts.round.donators,
+            ctx.accounts.config.minimum_required_vote_percentage,
+        );
+        if terminated {
+            msg!("Campaign terminated by vote, refunds are open");
+        }
+        ctx.accounts.campaign.end_round_vote(&mut ctx.accounts.round, terminated)?;
         //round.status = 200;
         round_votes.voting_ended = true;
         Ok(())
     }
 
 
-    pub fn start_next_round(ctx: Context<StartNextRound>, target: u64) -> Result<()> {
+    pub fn start_next_round(
+        ctx: Context<StartNextRound>,
+        target: u64,
+        deadline: Option<i64>,
+    ) -> Result<()> {
+        let current_time = clock::Clock::get().unwrap().unix_timestamp;
+        let round_deadline = validate_deadline(deadline, current_time)?;
         let campaign = &mut ctx.accounts.campaign;
         let round_target: u64;
 
@@ -269,21 +409,35 @@ pub mod pandora {
         round.balance = 0;
         round.donators = 0;
         round.status = RoundStatus::DonationsOpen.to_u8();
+        round.withdrawn = 0;
+        round.start_time = current_time;
+        round.deadline = round_deadline;
+        round.sqrt_donations = 0;
     
         Ok(())
     }
 
     pub fn withdraw(ctx: Context<Withdraw>) -> Result<()> {
         let campaign = &mut ctx.accounts.campaign;
+        let round = &mut ctx.accounts.round;
+
+        require!(campaign.releases(round), ErrorCode::RoundNotReleased);
+        let is_final_round = campaign.is_final_round(round);
+        let is_partial_raise = campaign.is_partial_raise(round);
+
+        // All-or-nothing campaigns pay out nothing until the whole target is raised
+        if FundingMode::from(campaign.funding_mode)? == FundingMode::AllOrNothing {
+            require!(campaign.balance >= campaign.target, ErrorCode::CampaignTargetNotMet);
+        }
 
         let fundstarter = ctx.accounts.fundstarter.to_owned();
         let funds_pot = &mut ctx.accounts.vault.to_owned();
         let destination_account = ctx.accounts.wallet_to_withdraw_to.to_owned();
         let token_program = ctx.accounts.token_program.to_owned();
 
-        // We reload to get the amount of tokens in our pot and withdraw all of it
-        funds_pot.reload()?;
-        let amount_to_withdraw = funds_pot.amount;
+        // Only what this round raised and hasn't been withdrawn yet
+        let amount_to_withdraw = round.balance.checked_sub(round.withdrawn).unwrap();
+        require!(amount_to_withdraw > 0, ErrorCode::NothingToWithdraw);
 
         let transfer_instruction = Transfer {
             from: funds_pot.to_account_info(),
@@ -291,9 +445,11 @@ pub mod pandora {
             authority: campaign.to_account_info(),
         };
 
+        let campaign_index = campaign.index.to_le_bytes();
         let campaign_seeds = &[
             b"campaign".as_ref(),
             fundstarter.key.as_ref(),
+            campaign_index.as_ref(),
             &[campaign.bump],
         ];
         let signer = &[&campaign_seeds[..]];
@@ -302,9 +458,14 @@ pub mod pandora {
             .with_signer(signer);
         anchor_spl::token::transfer(cpi_ctx, amount_to_withdraw)?;
 
-        //campaign.balance = campaign.balance.checked_sub(amount_to_withdraw).unwrap();
+        round.withdrawn = round.balance;
+
+        if is_final_round && !is_partial_raise {
+            campaign.status = CampaignStatus::CampaignEnded.to_u8();
+        }
 
-        let should_close = {
+        // Rounds can be withdrawn in any order, the vault is closed by whichever empties it last
+        let should_close = campaign.stopped_raising() && {
             funds_pot.reload()?;
             funds_pot.amount == 0
         };
@@ -320,12 +481,56 @@ pub mod pandora {
             anchor_spl::token::close_account(cpi_ctx)?;
         }
 
-        let current_round = campaign.active_round;
-        let total_rounds = campaign.total_rounds;
+        Ok(())
+    }
 
-        if current_round == total_rounds {
-            campaign.status = CampaignStatus::CampaignEnded.to_u8();
-        }
+    // Anyone can close a round whose deadline passed before its target was met
+    pub fn close_expired_round(ctx: Context<CloseExpiredRound>) -> Result<()> {
+        let current_time = clock::Clock::get().unwrap().unix_timestamp;
+        let round = &mut ctx.accounts.round;
+        require!(round.deadline_passed(current_time), ErrorCode::RoundNotExpired);
+
+        round.status = RoundStatus::RoundFailed.to_u8();
+
+        let campaign = &mut ctx.accounts.campaign;
+        campaign.can_start_next_round = false;
+        campaign.status = match FundingMode::from(campaign.funding_mode)? {
+            FundingMode::AllOrNothing => CampaignStatus::CampaignFailed.to_u8(),
+            // The fundstarter keeps what this round raised
+            FundingMode::Flexible => CampaignStatus::CampaignPartiallyFunded.to_u8(),
+        };
+
+        Ok(())
+    }
+
+    pub fn claim_refund(ctx: Context<ClaimRefund>) -> Result<()> {
+        let campaign = &mut ctx.accounts.campaign;
+        let round = &mut ctx.accounts.round;
+        let contribution = ctx.accounts.donator_account.amount;
+        let refund = campaign.refund_for(round.key(), round, contribution, ctx.accounts.vault.amount)?;
+
+        let campaign_index = campaign.index.to_le_bytes();
+        let campaign_seeds = &[
+            b"campaign".as_ref(),
+            campaign.fundstarter.as_ref(),
+            campaign_index.as_ref(),
+            &[campaign.bump],
+        ];
+        let signer = &[&campaign_seeds[..]];
+
+        anchor_spl::token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.vault.to_account_info(),
+                    to: ctx.accounts.donator_token_account.to_account_info(),
+                    authority: campaign.to_account_info(),
+                }
+            ).with_signer(signer),
+            refund
+        )?;
+
+        campaign.record_refund(round, contribution);
 
         Ok(())
     }
@@ -337,10 +542,33 @@ pub mod pandora {
         Ok(())
     }
 
-    pub fn stake(ctx: Context<Stake>, amount: u64) -> Result<()> {
+    pub fn withdraw_treasury(ctx: Context<WithdrawTreasury>, amount: u64) -> Result<()> {
+        let config_bump = ctx.accounts.config.bump;
+        let config_seeds = &["config".as_bytes().as_ref(), &[config_bump]];
+        let signer = &[&config_seeds[..]];
+
+        anchor_spl::token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.treasury.to_account_info(),
+                    to: ctx.accounts.destination.to_account_info(),
+                    authority: ctx.accounts.config.to_account_info(),
+                }
+            ).with_signer(signer),
+            amount
+        )?;
+
+        Ok(())
+    }
+
+    // A non-zero lock keeps the stake from being unstaked until it ends, in exchange for a voting boost
+    pub fn stake(ctx: Context<Stake>, amount: u64, lock_duration_in_seconds: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidStakeAmount);
+        require!(lock_duration_in_seconds <= MAX_STAKE_LOCK_IN_SECONDS, ErrorCode::InvalidLockDuration);
+
         let token_program = &ctx.accounts.token_program;
         let clock = clock::Clock::get().unwrap();
-        let stake_account = &mut ctx.accounts.stake_account;
 
         anchor_spl::token::transfer(
             CpiContext::new(
@@ -355,26 +583,55 @@ pub mod pandora {
         )?;
 
         //stake_account.staker = ctx.accounts.staker.key(); 
-        stake_account.stake_time = clock.unix_timestamp;
-        stake_account.deposit = amount;
-        stake_account.reward = 0;
+        ctx.accounts.config.open_stake(
+            &mut ctx.accounts.stake_account,
+            amount,
+            clock.unix_timestamp,
+            clock.unix_timestamp + lock_duration_in_seconds as i64,
+        );
 
-        let config = &mut ctx.accounts.config;
-        config.active_stakers = config.active_stakers
-            .checked_add(1).unwrap();
-        config.total_amount_staked = config.total_amount_staked
-            .checked_add(amount).unwrap();
+        Ok(())
+    }
+
+    // Also lets existing stakers opt in to the lock boost, with or without adding tokens
+    pub fn increase_stake(ctx: Context<IncreaseStake>, amount: u64, lock_duration_in_seconds: u64) -> Result<()> {
+        require!(amount > 0 || lock_duration_in_seconds > 0, ErrorCode::InvalidStakeAmount);
+        require!(lock_duration_in_seconds <= MAX_STAKE_LOCK_IN_SECONDS, ErrorCode::InvalidLockDuration);
+        let clock = clock::Clock::get().unwrap();
+        ctx.accounts.stake_account.extend_lock(clock.unix_timestamp + lock_duration_in_seconds as i64);
+        if amount == 0 {
+            return Ok(());
+        }
+
+        anchor_spl::token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.staker_token_account.to_account_info(),
+                    to: ctx.accounts.staking_pool.to_account_info(),
+                    authority: ctx.accounts.staker.to_account_info(),
+                }
+            ),
+            amount
+        )?;
+
+        ctx.accounts.config.add_stake(&mut ctx.accounts.stake_account, amount, clock.unix_timestamp);
 
         Ok(())
     }
 
-    pub fn unstake(ctx: Context<Unstake>) -> Result<()> {
+    // Voting power drops right away, the tokens can be withdrawn once the cooldown is over
+    pub fn request_unstake(ctx: Context<RequestUnstake>, amount: u64) -> Result<()> {
+        let current_time = clock::Clock::get().unwrap().unix_timestamp;
+        ctx.accounts.config.request_unstake(&mut ctx.accounts.stake_account, amount, current_time)
+    }
+
+    pub fn complete_unstake(ctx: Context<CompleteUnstake>) -> Result<()> {
+        let current_time = clock::Clock::get().unwrap().unix_timestamp;
+        let (payout, closing) = ctx.accounts.stake_account.complete_unstake(current_time)?;
+
         let config_bump = ctx.accounts.config.bump;
-        let config_seeds = &[
-            "config".as_bytes().as_ref(),
-            ctx.accounts.config.admin.as_ref(),
-             &[config_bump]
-        ];
+        let config_seeds = &["config".as_bytes().as_ref(), &[config_bump]];
         let signer = &[&config_seeds[..]];
         
         anchor_spl::token::transfer(
@@ -386,33 +643,126 @@ pub mod pandora {
                     authority: ctx.accounts.config.to_account_info(),
                 }
             ).with_signer(signer),
-            ctx.accounts.stake_account.deposit
+            payout
         )?;
 
-        let config = &mut ctx.accounts.config;
-        config.active_stakers = config.active_stakers
-            .checked_add(1).unwrap();
-        config.total_amount_staked = config.total_amount_staked
-            .checked_add(ctx.accounts.stake_account.deposit).unwrap();
+        if closing {
+            ctx.accounts.stake_account.close(ctx.accounts.staker.to_account_info())?;
+        }
 
         Ok(())
     }
 
-    pub fn init_donator_voting(ctx: Context<DonatorVotingInit>) -> Result<()> {
+    // Moves native token fees from the treasury to the staking pool, split pro-rata between stakers
+    pub fn fund_rewards(ctx: Context<FundRewards>, amount: u64) -> Result<()> {
+        require!(amount > 0, ErrorCode::InvalidRewardAmount);
+        require!(ctx.accounts.config.total_amount_staked > 0, ErrorCode::NoStakers);
 
-        let total_donations = ctx.accounts.round.balance;
-        let donation = ctx.accounts.donator_account.amount;
-        let donator_voting_rights = ctx.accounts.config.donator_voting_rights;
+        let config_bump = ctx.accounts.config.bump;
+        let config_seeds = &["config".as_bytes().as_ref(), &[config_bump]];
+        let signer = &[&config_seeds[..]];
 
-        let voting_power = donation
-            .checked_mul(donator_voting_rights as u64)
-            .unwrap()
-            .checked_div(total_donations)
-            .unwrap();
+        anchor_spl::token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.treasury.to_account_info(),
+                    to: ctx.accounts.staking_pool.to_account_info(),
+                    authority: ctx.accounts.config.to_account_info(),
+                }
+            ).with_signer(signer),
+            amount
+        )?;
+
+        ctx.accounts.config.distribute_rewards(amount);
+
+        Ok(())
+    }
+
+    pub fn claim_rewards(ctx: Context<ClaimRewards>) -> Result<()> {
+        let reward_per_token = ctx.accounts.config.reward_per_token;
+        let reward = ctx.accounts.stake_account.claim_rewards(reward_per_token)?;
+
+        let config_bump = ctx.accounts.config.bump;
+        let config_seeds = &["config".as_bytes().as_ref(), &[config_bump]];
+        let signer = &[&config_seeds[..]];
+
+        anchor_spl::token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Transfer {
+                    from: ctx.accounts.staking_pool.to_account_info(),
+                    to: ctx.accounts.staker_token_account.to_account_info(),
+                    authority: ctx.accounts.config.to_account_info(),
+                }
+            ).with_signer(signer),
+            reward
+        )?;
+
+        Ok(())
+    }
+
+    // Lends the whole deposit's voting and moderation power to another staker until revoked
+    pub fn delegate_stake(ctx: Context<DelegateStake>) -> Result<()> {
+        let current_time = clock::Clock::get().unwrap().unix_timestamp;
+        let stake_account = &mut ctx.accounts.stake_account;
+        let delegate_stake_account = &mut ctx.accounts.delegate_stake_account;
+
+        // Stake already backing votes would be counted twice
+        require!(!stake_account.backs_votes(current_time), ErrorCode::StakeLocked);
+        require!(stake_account.deposit > 0, ErrorCode::InvalidStakeAmount);
+        // Delegations don't chain, power lent to a delegator or to a delegating staker couldn't be used
+        require!(
+            stake_account.delegated_stake == 0
+                && !delegate_stake_account.delegated
+                && delegate_stake_account.deposit > 0,
+            ErrorCode::InvalidDelegate
+        );
+
+        let delegation = &mut ctx.accounts.delegation;
+        delegation.delegator = ctx.accounts.delegator.key();
+        delegation.delegate = ctx.accounts.delegate.key();
+        delegation.amount = stake_account.deposit;
+        delegation.delegated_at = current_time;
+        delegation.bump = *ctx.bumps.get("delegation").unwrap();
+
+        stake_account.delegated = true;
+        delegate_stake_account.delegated_stake = delegate_stake_account.delegated_stake
+            .checked_add(delegation.amount).unwrap();
+
+        Ok(())
+    }
+
+    // Revoking takes effect right away, votes the delegate already cast with the stake keep counting
+    // and hold it until they can't change anymore
+    pub fn revoke_delegation(ctx: Context<RevokeDelegation>) -> Result<()> {
+        let current_time = clock::Clock::get().unwrap().unix_timestamp;
+        let delegate_stake_account = &mut ctx.accounts.delegate_stake_account;
+        delegate_stake_account.delegated_stake = delegate_stake_account.delegated_stake
+            .checked_sub(ctx.accounts.delegation.amount).unwrap();
+
+        let stake_account = &mut ctx.accounts.stake_account;
+        stake_account.delegated = false;
+        stake_account.last_revocation_time = current_time;
+        stake_account.inherit_locks(delegate_stake_account);
+
+        Ok(())
+    }
+
+    pub fn init_donator_voting(ctx: Context<DonatorVotingInit>) -> Result<()> {
+
+        let donator_power = donator_voting_power(
+            ctx.accounts.donator_account.amount,
+            &ctx.accounts.round,
+            ctx.accounts.campaign.quadratic_voting,
+            ctx.accounts.config.donator_voting_rights,
+        );
 
         let voter_account = &mut ctx.accounts.voter_account;
-        voter_account.voting_power = voting_power as u8;
+        voter_account.voting_power = donator_power;
         voter_account.has_voted = false;
+        voter_account.choice = None;
+        voter_account.commitment = None;
         voter_account.voter_type = VoterType::Donator.to_u8();
         voter_account.bump = *ctx.bumps.get("voter_account").unwrap();
 
@@ -421,19 +771,42 @@ pub mod pandora {
 
     pub fn init_staker_voting(ctx: Context<StakerVotingInit>) -> Result<()> {
 
-        let staker_deposit = ctx.accounts.stake_account.deposit;
-        let total_amount_staked = ctx.accounts.config.total_amount_staked;
-        let staker_voting_rights = ctx.accounts.config.staker_voting_rights;
+        let round_votes = &ctx.accounts.round_votes;
+        require!(
+            ctx.accounts.stake_account.last_deposit_time <= round_votes.start_time,
+            ErrorCode::StakedAfterSnapshot
+        );
 
-        let voting_power = staker_deposit
-            .checked_mul(staker_voting_rights as u64)
-            .unwrap()
-            .checked_div(total_amount_staked)
-            .unwrap();
+        require!(!ctx.accounts.stake_account.delegated, ErrorCode::StakeDelegated);
+        require!(
+            ctx.accounts.stake_account.undelegated_since(round_votes.start_time),
+            ErrorCode::DelegatedWhenOpened
+        );
+
+        let delegated_stake = delegated_stake_at(
+            ctx.accounts.staker.key(),
+            ctx.remaining_accounts,
+            round_votes.start_time,
+        )?;
+
+        // Boosts are taken at the snapshot, so every staker in a round is weighed at the same time
+        let staker_power = staker_voting_power(
+            &ctx.accounts.stake_account,
+            delegated_stake,
+            round_votes.total_amount_staked,
+            ctx.accounts.config.staker_voting_rights,
+            round_votes.start_time,
+        );
+
+        // The stake backing this ballot can't leave before voting ends
+        let stake_account = &mut ctx.accounts.stake_account;
+        stake_account.locked_until = stake_account.locked_until.max(round_votes.reveal_end_time);
 
         let voter_account = &mut ctx.accounts.voter_account;
-        voter_account.voting_power = voting_power as u8;
+        voter_account.voting_power = staker_power;
         voter_account.has_voted = false;
+        voter_account.choice = None;
+        voter_account.commitment = None;
         voter_account.voter_type = VoterType::Staker.to_u8();
         voter_account.bump = *ctx.bumps.get("voter_account").unwrap();
 
@@ -441,26 +814,43 @@ pub mod pandora {
     }
 
     pub fn init_staker_moderation(ctx: Context<StakerModerationInit>) -> Result<()> {
-        let staker_deposit = ctx.accounts.stake_account.deposit;
-        let total_amount_staked = ctx.accounts.config.total_amount_staked;
-        let staker_moderation_rights = ctx.accounts.config.staker_moderation_rights;
-
-        let voting_power = staker_deposit
-            .checked_mul(staker_moderation_rights as u64)
-            .unwrap()
-            .checked_div(total_amount_staked)
-            .unwrap();
+        // Moderation is open for as long as the campaign runs, so it counts from the campaign's start
+        let campaign_start_time = ctx.accounts.campaign.start_time;
+        require!(!ctx.accounts.stake_account.delegated, ErrorCode::StakeDelegated);
+        require!(
+            ctx.accounts.stake_account.undelegated_since(campaign_start_time),
+            ErrorCode::DelegatedWhenOpened
+        );
 
-        let moderator_account = &mut ctx.accounts.moderator_account;
-        moderator_account.voting_power = voting_power as u8;
-        moderator_account.has_voted = false;
+        let clock = clock::Clock::get().unwrap();
+        require!(
+            !ctx.accounts.campaign.moderation_closed(clock.unix_timestamp),
+            ErrorCode::ModerationClosed
+        );
+        let staker_power = staker_voting_power(
+            &ctx.accounts.stake_account,
+            ctx.accounts.stake_account.delegated_stake,
+            ctx.accounts.config.total_amount_staked,
+            ctx.accounts.config.staker_moderation_rights,
+            clock.unix_timestamp,
+        );
+
+        let moderator_account = &mut ctx.accounts.moderator_account;
+        moderator_account.voting_power = staker_power;
+        moderator_account.has_voted = false;
+        moderator_account.thumbs_up = false;
         moderator_account.moderator_type = ModeratorType::Staker.to_u8();
-        
+
+        let moderation_end_time = ctx.accounts.campaign.moderation_end_time;
+        ctx.accounts.stake_account.add_moderation(moderation_end_time);
+
         Ok(())
     }
 
     pub fn moderate(ctx: Context<Moderate>, thumbs_up: bool ) -> Result<()> {
+        let current_time = clock::Clock::get().unwrap().unix_timestamp;
         let campaign = &mut ctx.accounts.campaign;
+        require!(!campaign.moderation_closed(current_time), ErrorCode::ModerationClosed);
         let moderator_account = &mut ctx.accounts.moderator_account;
 
         match thumbs_up {
@@ -477,26 +867,253 @@ pub mod pandora {
         }
 
         moderator_account.has_voted = true;
+        moderator_account.thumbs_up = thumbs_up;
         campaign.moderator_votes = campaign.moderator_votes
             .checked_add(1).unwrap();
 
-        // at least 30% of all moderators(for now stakers only) must vote
+        // a minimum percentage of all moderators(for now stakers only) must vote
         // for a campaign to be stopped successfully.
-        let minimum_votes_required = (30 as u64)
-            .checked_mul(ctx.accounts.config.active_stakers)
-            .unwrap()
-            .checked_div(100 as u64)
-            .unwrap();
-        let voters_this_round = campaign.moderator_votes;
+        let quorum_reached = quorum_reached(
+            campaign.moderator_votes,
+            ctx.accounts.config.active_stakers,
+            ctx.accounts.config.minimum_moderation_vote_percentage,
+        );
 
-        if campaign.not_valid_votes > campaign.is_valid_votes && voters_this_round > minimum_votes_required {
+        if campaign.not_valid_votes > campaign.is_valid_votes && quorum_reached {
+            // Final: donors of every round can now reclaim what's left in the vault
+            msg!("Campaign flagged as fraudulent, refunds are open");
             campaign.is_valid_campaign = false;
+            campaign.status = CampaignStatus::CampaignFrozen.to_u8();
         } else {
             campaign.is_valid_campaign = true;
         }
 
         Ok(())
     }
+
+    // Unlocks the stake backing a moderation once moderation on the campaign has closed.
+    pub fn release_moderation(ctx: Context<ReleaseModeration>) -> Result<()> {
+        let current_time = clock::Clock::get().unwrap().unix_timestamp;
+        require!(
+            ctx.accounts.campaign.moderation_closed(current_time),
+            ErrorCode::ModerationStillActive
+        );
+        let campaign_frozen = ctx.accounts.campaign.status == CampaignStatus::CampaignFrozen.to_u8();
+        let slashed = ctx.accounts.moderator_account.voted_against(campaign_frozen);
+        let slash = ctx.accounts.config.release_moderation(&mut ctx.accounts.stake_account, slashed)?;
+
+        if slash > 0 {
+            let config_bump = ctx.accounts.config.bump;
+            let config_seeds = &["config".as_bytes().as_ref(), &[config_bump]];
+            let signer = &[&config_seeds[..]];
+
+            anchor_spl::token::transfer(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    Transfer {
+                        from: ctx.accounts.staking_pool.to_account_info(),
+                        to: ctx.accounts.treasury.to_account_info(),
+                        authority: ctx.accounts.config.to_account_info(),
+                    }
+                ).with_signer(signer),
+                slash
+            )?;
+
+            emit!(ModeratorSlashed {
+                campaign: ctx.accounts.campaign.key(),
+                staker: ctx.accounts.staker.key(),
+                amount: slash,
+            });
+        }
+
+        Ok(())
+    }
+
+    // Rewrites a RoundVote created before tallies were stored in basis points
+    pub fn migrate_round_vote(ctx: Context<MigrateRoundVote>) -> Result<()> {
+        let account = ctx.accounts.round_votes.to_account_info();
+        let legacy = {
+            let data = account.try_borrow_data()?;
+            require!(
+                data.len() == 8 + LegacyRoundVote::SIZE && data[..8] == RoundVote::discriminator(),
+                ErrorCode::AccountAlreadyMigrated
+            );
+            LegacyRoundVote::deserialize(&mut &data[8..])?
+        };
+
+        // legacy ballots ran for whatever period the config holds now
+        let end_time = legacy.start_time
+            .checked_add(ctx.accounts.config.round_voting_period())
+            .unwrap();
+        let round_votes = RoundVote {
+            continue_campaign: (legacy.continue_campaign as u64) * BASIS_POINTS_PER_PERCENT,
+            terminate_campaign: (legacy.terminate_campaign as u64) * BASIS_POINTS_PER_PERCENT,
+            donators_voted: legacy.donators_voted,
+            stakers_voted: legacy.stakers_voted,
+            start_time: legacy.start_time,
+            end_time,
+            // ballots were public before commit-reveal existed
+            reveal_end_time: end_time,
+            voting_ended: legacy.voting_ended,
+            total_amount_staked: ctx.accounts.config.total_amount_staked,
+            active_stakers: ctx.accounts.config.active_stakers,
+        };
+
+        grow_account(&account, 8 + RoundVote::SIZE, &ctx.accounts.payer, &ctx.accounts.system_program)?;
+        let mut data = account.try_borrow_mut_data()?;
+        round_votes.try_serialize(&mut &mut data[..])?;
+
+        Ok(())
+    }
+
+    // Rewrites a NextRoundVoter created before voting power was stored in basis points
+    pub fn migrate_next_round_voter(ctx: Context<MigrateNextRoundVoter>) -> Result<()> {
+        let account = ctx.accounts.voter_account.to_account_info();
+        let legacy = {
+            let data = account.try_borrow_data()?;
+            require!(
+                data.len() == 8 + LegacyNextRoundVoter::SIZE
+                    && data[..8] == NextRoundVoter::discriminator(),
+                ErrorCode::AccountAlreadyMigrated
+            );
+            LegacyNextRoundVoter::deserialize(&mut &data[8..])?
+        };
+
+        let voter_account = NextRoundVoter {
+            voting_power: (legacy.voting_power as u64) * BASIS_POINTS_PER_PERCENT,
+            has_voted: legacy.has_voted,
+            choice: None,
+            commitment: None,
+            voter_type: legacy.voter_type,
+            bump: legacy.bump,
+        };
+
+        grow_account(&account, 8 + NextRoundVoter::SIZE, &ctx.accounts.payer, &ctx.accounts.system_program)?;
+        let mut data = account.try_borrow_mut_data()?;
+        voter_account.try_serialize(&mut &mut data[..])?;
+
+        Ok(())
+    }
+}
+
+// Deadlines are optional, 0 is stored when a round has none
+fn validate_deadline(deadline: Option<i64>, current_time: i64) -> Result<i64> {
+    match deadline {
+        Some(deadline) => {
+            require!(deadline > current_time, ErrorCode::InvalidDeadline);
+            Ok(deadline)
+        }
+        None => Ok(0),
+    }
+}
+
+// Share of `rights`% of the vote held by `share` out of `total`, in basis points
+fn voting_power(share: u128, total: u128, rights: u8) -> u64 {
+    if total == 0 {
+        return 0;
+    }
+    (share * (rights as u128) * (BASIS_POINTS_PER_PERCENT as u128) / total) as u64
+}
+
+// Quadratic voting weighs donators by the square root of their donation,
+// so a single large donation can't outvote many small ones
+fn donator_voting_power(donation: u64, round: &Round, quadratic_voting: bool, rights: u8) -> u64 {
+    if quadratic_voting {
+        voting_power(isqrt(donation) as u128, round.sqrt_donations as u128, rights)
+    } else {
+        voting_power(donation as u128, round.balance as u128, rights)
+    }
+}
+
+// Floor of the square root
+fn isqrt(n: u64) -> u64 {
+    if n < 2 {
+        return n;
+    }
+    // Newton's method, starting from a power of two above the root so it only descends
+    let bits = 64 - n.leading_zeros();
+    let mut x = 1u64 << (bits / 2 + 1);
+    loop {
+        let y = (x + n / x) / 2;
+        if y >= x {
+            return x;
+        }
+        x = y;
+    }
+}
+
+// An unboosted stake holds its share of `rights`, boosts multiply that but never past `rights`.
+// Delegated stake votes without its owner's boosts
+fn staker_voting_power(
+    stake_account: &StakeAccount,
+    delegated_stake: u64,
+    total_amount_staked: u64,
+    rights: u8,
+    at: i64,
+) -> u64 {
+    let weight = (stake_account.deposit as u128) * (stake_account.boost(at) as u128)
+        + (delegated_stake as u128) * (MAX_BASIS_POINTS as u128);
+
+    voting_power(
+        weight,
+        (total_amount_staked as u128) * (MAX_BASIS_POINTS as u128),
+        rights,
+    ).min(rights as u64 * BASIS_POINTS_PER_PERCENT)
+}
+
+// Stake delegated to `delegate` before `opened_at`, out of the Delegation accounts the delegate passes along.
+// Leaving some out only lowers the delegate's own voting power
+fn delegated_stake_at(delegate: Pubkey, delegations: &[AccountInfo], opened_at: i64) -> Result<u64> {
+    let mut counted: Vec<Pubkey> = Vec::with_capacity(delegations.len());
+    let mut delegated_stake: u64 = 0;
+    for info in delegations {
+        let delegation = Account::<Delegation>::try_from(info)?;
+        require!(
+            delegation.delegate == delegate && !counted.contains(info.key),
+            ErrorCode::InvalidDelegation
+        );
+        counted.push(*info.key);
+        if delegation.counts_at(opened_at) {
+            delegated_stake = delegated_stake.checked_add(delegation.amount).unwrap();
+        }
+    }
+    Ok(delegated_stake)
+}
+
+// Hash a commit-reveal ballot is committed as, bound to its voter and vote so it can't be replayed elsewhere
+fn ballot_commitment(voter: &Pubkey, round_votes: &Pubkey, continue_campaign: bool, salt: &[u8; 32]) -> [u8; 32] {
+    hash::hashv(&[voter.as_ref(), round_votes.as_ref(), &[continue_campaign as u8], salt]).to_bytes()
+}
+
+// Grows a program account to `new_len`, topping up rent from `payer`
+fn grow_account<'info>(
+    account: &AccountInfo<'info>,
+    new_len: usize,
+    payer: &Signer<'info>,
+    system_program: &Program<'info, System>,
+) -> Result<()> {
+    let required_lamports = Rent::get()?.minimum_balance(new_len);
+    let top_up = required_lamports.saturating_sub(account.lamports());
+    if top_up > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.to_account_info(),
+                system_program::Transfer {
+                    from: payer.to_account_info(),
+                    to: account.clone(),
+                }
+            ),
+            top_up
+        )?;
+    }
+    account.realloc(new_len, false)?;
+
+    Ok(())
+}
+
+// True when more than `percentage`% of the eligible voters took part
+fn quorum_reached(voters: u64, eligible_voters: u64, percentage: u8) -> bool {
+    (voters as u128) * 100 > (eligible_voters as u128) * (percentage as u128)
 }
 
 #[derive(Accounts)]
@@ -517,13 +1134,68 @@ pub struct Initialize<'info> {
     rent: Sysvar<'info, Rent>
 }
 
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(
+        mut,
+        seeds = ["config".as_bytes().as_ref()],
+        bump = config.bump,
+        has_one = admin @ErrorCode::Unauthorized,
+    )]
+    config: Account<'info, Config>,
+    admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ProposeAdmin<'info> {
+    #[account(
+        mut,
+        seeds = ["config".as_bytes().as_ref()],
+        bump = config.bump,
+        has_one = admin @ErrorCode::Unauthorized,
+    )]
+    config: Account<'info, Config>,
+    admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAdmin<'info> {
+    #[account(
+        mut,
+        seeds = ["config".as_bytes().as_ref()],
+        bump = config.bump,
+        constraint = config.pending_admin != Pubkey::default() @ErrorCode::NotPendingAdmin,
+        constraint = config.pending_admin == pending_admin.key() @ErrorCode::NotPendingAdmin,
+    )]
+    config: Account<'info, Config>,
+    pending_admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RenounceAdmin<'info> {
+    #[account(
+        mut,
+        seeds = ["config".as_bytes().as_ref()],
+        bump = config.bump,
+        has_one = admin @ErrorCode::Unauthorized,
+    )]
+    config: Account<'info, Config>,
+    admin: Signer<'info>,
+}
+
 
 #[derive(Accounts)]
 pub struct StartCampaign<'info> {
     #[account(mut)]
     fundstarter: Signer<'info>,
     #[account(
-        init, seeds = [b"campaign".as_ref(), fundstarter.key().as_ref()],
+        init_if_needed, seeds = [b"creator".as_ref(), fundstarter.key().as_ref()],
+        bump, payer = fundstarter, space = 8 + CreatorProfile::SIZE
+    )]
+    creator_profile: Account<'info, CreatorProfile>,
+    #[account(
+        init,
+        seeds = [b"campaign".as_ref(), fundstarter.key().as_ref(), creator_profile.campaign_count.to_le_bytes().as_ref()],
         bump, payer = fundstarter, space = 8 + Campaign::SIZE
     )]
     campaign: Account<'info, Campaign>,
@@ -538,6 +1210,16 @@ pub struct StartCampaign<'info> {
         bump, payer = fundstarter, space = 8 + Round::SIZE
     )]
     round: Account<'info, Round>,
+    #[account(seeds = ["config".as_bytes().as_ref()], bump = config.bump)]
+    config: Account<'info, Config>,
+    // One treasury per mint, collects the donation fees of campaigns in that mint.
+    // The first campaign in a mint creates it, so donations never wait on the admin
+    #[account(
+        init_if_needed, payer = fundstarter,
+        seeds = ["treasury".as_bytes().as_ref(), config.key().as_ref(), token_mint.key().as_ref()],
+        bump, token::mint = token_mint, token::authority = config
+    )]
+    treasury: Account<'info, TokenAccount>,
     
     token_mint: Account<'info, Mint>,
     system_program: Program<'info, System>,
@@ -550,7 +1232,12 @@ pub struct StartCampaign<'info> {
 #[derive(Accounts)]
 pub struct InitializeVoting<'info> {
     #[account(
-        mut, seeds = [b"campaign".as_ref(), fundstarter.key().as_ref()],
+        seeds = ["config".as_bytes().as_ref()],
+        bump = config.bump
+    )]
+    config: Account<'info, Config>,
+    #[account(
+        mut, seeds = [b"campaign".as_ref(), fundstarter.key().as_ref(), campaign.index.to_le_bytes().as_ref()],
         bump = campaign.bump, has_one = fundstarter, has_one = vault,
         constraint = campaign.active_round_address == round.key()
     )]
@@ -580,7 +1267,12 @@ pub struct TallyVotes<'info> {
     )]
     config: Account<'info, Config>,
 
-    #[account(mut,constraint = campaign.active_round_address == round.key())]
+    // Moderation may have settled the campaign while the vote was open, its outcome stands
+    #[account(
+        mut,
+        constraint = campaign.active_round_address == round.key(),
+        constraint = !campaign.is_concluded() @ErrorCode::CampaignInactive,
+    )]
     campaign: Account<'info, Campaign>,
 
     #[account(mut, has_one = round_votes, constraint = round.status == RoundStatus::RoundTargetMet.to_u8())]
@@ -596,10 +1288,11 @@ pub struct StartNextRound<'info> {
     #[account(mut)]
     fundstarter: Signer<'info>,
     #[account(
-        mut, seeds = [b"campaign".as_ref(), fundstarter.key().as_ref()],
+        mut, seeds = [b"campaign".as_ref(), fundstarter.key().as_ref(), campaign.index.to_le_bytes().as_ref()],
         bump = campaign.bump, has_one = fundstarter,
         constraint = campaign.active_round != campaign.total_rounds @ErrorCode::CantExceedMaxRound,
         constraint = campaign.can_start_next_round == true @ErrorCode::CantStartNextRound,
+        constraint = !campaign.is_concluded() @ErrorCode::CampaignInactive,
     )]
     campaign: Account<'info, Campaign>,
 
@@ -621,6 +1314,12 @@ pub struct StartNextRound<'info> {
 
 #[derive(Accounts)]
 pub struct Donate<'info> {
+    #[account(
+        seeds = ["config".as_bytes().as_ref()],
+        bump = config.bump
+    )]
+    config: Account<'info, Config>,
+
     #[account(
         mut, has_one = vault, 
         constraint = campaign.active_round_address == round.key()
@@ -630,11 +1329,19 @@ pub struct Donate<'info> {
     #[account(mut)]
     vault: Account<'info, TokenAccount>,
 
+    #[account(
+        mut,
+        seeds = ["treasury".as_bytes().as_ref(), config.key().as_ref(), campaign.token_mint.as_ref()],
+        bump,
+    )]
+    treasury: Account<'info, TokenAccount>,
+
     #[account(mut)]
     round: Account<'info, Round>,
 
+    // Seeded by round and donator, so an existing account can only ever be this donator's
     #[account(
-        init, space = 8 + Donator::SIZE, payer = donator,
+        init_if_needed, space = 8 + Donator::SIZE, payer = donator,
         seeds = [b"donator".as_ref(), round.key().as_ref(), donator.key().as_ref()],
         bump
     )]
@@ -657,12 +1364,18 @@ pub struct Donate<'info> {
 #[derive(Accounts)]
 pub struct Withdraw<'info> {
     #[account(
-        mut, seeds=[b"campaign".as_ref(), fundstarter.key().as_ref()], bump = campaign.bump,
+        mut, seeds=[b"campaign".as_ref(), fundstarter.key().as_ref(), campaign.index.to_le_bytes().as_ref()],
+        bump = campaign.bump,
         has_one = fundstarter, has_one = vault,
-        constraint = campaign.is_valid_campaign == true
+        constraint = campaign.is_valid_campaign == true,
     )]
     campaign: Account<'info, Campaign>,
 
+    #[account(
+        mut,
+        seeds = [b"round".as_ref(), campaign.key().as_ref(), (round.round as u64).to_le_bytes().as_ref()],
+        bump
+    )]
     round: Account<'info, Round>,
 
     #[account(mut)]
@@ -681,6 +1394,55 @@ pub struct Withdraw<'info> {
     token_program: Program<'info, Token>,
 }
 
+#[derive(Accounts)]
+pub struct CloseExpiredRound<'info> {
+    #[account(
+        mut,
+        constraint = campaign.active_round_address == round.key(),
+        constraint = campaign.status == CampaignStatus::CampaignActive.to_u8() @ErrorCode::CampaignInactive
+    )]
+    campaign: Account<'info, Campaign>,
+
+    #[account(mut, constraint = round.status == RoundStatus::DonationsOpen.to_u8() @ErrorCode::RoundClosedToDonations)]
+    round: Account<'info, Round>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimRefund<'info> {
+    #[account(mut, has_one = vault)]
+    campaign: Account<'info, Campaign>,
+
+    #[account(
+        mut,
+        seeds = [b"round".as_ref(), campaign.key().as_ref(), (round.round as u64).to_le_bytes().as_ref()],
+        bump
+    )]
+    round: Account<'info, Round>,
+
+    #[account(mut)]
+    vault: Account<'info, TokenAccount>,
+
+    // Closed once refunded so the same contribution can't be claimed twice
+    #[account(
+        mut, close = donator,
+        seeds = [b"donator".as_ref(), round.key().as_ref(), donator.key().as_ref()],
+        bump = donator_account.bump,
+    )]
+    donator_account: Account<'info, Donator>,
+
+    #[account(mut)]
+    donator: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = donator_token_account.mint == campaign.token_mint,
+        constraint = donator_token_account.owner == donator.key()
+    )]
+    donator_token_account: Account<'info, TokenAccount>,
+
+    token_program: Program<'info, Token>,
+}
+
 
 #[derive(Accounts)]
 pub struct DonatorVotingInit<'info> {
@@ -726,12 +1488,14 @@ pub struct StakerVotingInit<'info> {
     #[account(mut,constraint = campaign.active_round_address == round.key())]
     campaign: Account<'info, Campaign>,
 
-    #[account(constraint = round.status == RoundStatus::RoundTargetMet.to_u8())]
+    #[account(has_one = round_votes, constraint = round.status == RoundStatus::RoundTargetMet.to_u8())]
     round: Account<'info, Round>,
+    round_votes: Account<'info, RoundVote>,
     #[account(mut)]
     staker: Signer<'info>,
 
     #[account(
+        mut,
         seeds = ["staker".as_bytes().as_ref(), staker.key().as_ref()],
         bump,
     )]
@@ -760,7 +1524,6 @@ pub struct VoteNextRound<'info> {
         mut,
         seeds = ["voter".as_bytes().as_ref(), round.key().as_ref(), voter.key().as_ref()],
         bump = voter_account.bump,
-        constraint = voter_account.has_voted == false
     )]
     voter_account: Account<'info, NextRoundVoter>,
 
@@ -778,7 +1541,6 @@ pub struct StakerModerationInit<'info> {
     )]
     config: Account<'info, Config>,
 
-    #[account(constraint = campaign.status != CampaignStatus::CampaignEnded.to_u8())]
     campaign: Account<'info, Campaign>,
 
     #[account(
@@ -794,6 +1556,7 @@ pub struct StakerModerationInit<'info> {
     staker: Signer<'info>,
 
     #[account(
+        mut,
         seeds = ["staker".as_bytes().as_ref(), staker.key().as_ref()],
         bump,
     )]
@@ -802,16 +1565,57 @@ pub struct StakerModerationInit<'info> {
 }
 
 #[derive(Accounts)]
-pub struct Moderate<'info> {
+pub struct ReleaseModeration<'info> {
     #[account(
+        mut,
         seeds = ["config".as_bytes().as_ref()],
-        bump = config.bump
+        bump = config.bump,
+        has_one = staking_pool
     )]
     config: Account<'info, Config>,
+
+    campaign: Account<'info, Campaign>,
+
+    #[account(
+        mut,
+        seeds = ["moderator".as_bytes().as_ref(), campaign.key().as_ref(), staker.key().as_ref()],
+        bump,
+        close = staker
+    )]
+    moderator_account: Account<'info, Moderator>,
+
+    /// CHECK: only receives the moderator account's rent, the seeds above tie it to the moderator
+    #[account(mut)]
+    staker: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = ["staker".as_bytes().as_ref(), staker.key().as_ref()],
+        bump,
+    )]
+    stake_account: Account<'info, StakeAccount>,
+
+    #[account(mut)]
+    staking_pool: Account<'info, TokenAccount>,
+
+    // Slashed stake goes to the native token treasury
     #[account(
         mut,
-        constraint = campaign.status != CampaignStatus::CampaignEnded.to_u8()
+        seeds = ["treasury".as_bytes().as_ref(), config.key().as_ref(), config.native_token_mint.as_ref()],
+        bump,
+    )]
+    treasury: Account<'info, TokenAccount>,
+    token_program: Program<'info, Token>
+}
+
+#[derive(Accounts)]
+pub struct Moderate<'info> {
+    #[account(
+        seeds = ["config".as_bytes().as_ref()],
+        bump = config.bump
     )]
+    config: Account<'info, Config>,
+    #[account(mut)]
     campaign: Account<'info, Campaign>,
     #[account(
         mut,
@@ -853,6 +1657,26 @@ pub struct InitializeStaking<'info> {
     rent: Sysvar<'info, Rent>
 }
 
+#[derive(Accounts)]
+pub struct WithdrawTreasury<'info> {
+    #[account(
+        seeds = ["config".as_bytes().as_ref()],
+        bump = config.bump,
+        has_one = admin @ErrorCode::Unauthorized,
+    )]
+    config: Account<'info, Config>,
+    admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = ["treasury".as_bytes().as_ref(), config.key().as_ref(), treasury.mint.as_ref()],
+        bump,
+    )]
+    treasury: Account<'info, TokenAccount>,
+    #[account(mut, constraint = destination.mint == treasury.mint)]
+    destination: Account<'info, TokenAccount>,
+    token_program: Program<'info, Token>
+}
+
 #[derive(Accounts)]
 pub struct Stake<'info> {
     #[account(
@@ -899,34 +1723,139 @@ pub struct Stake<'info> {
 
 
 #[derive(Accounts)]
-pub struct Unstake<'info> {
+pub struct IncreaseStake<'info> {
     #[account(
         mut,
         seeds = ["config".as_bytes().as_ref()],
         bump = config.bump,
-        has_one = staking_pool
+        has_one = staking_pool,
+        constraint = config.native_token_mint == mint.key(),
     )]
     config: Account<'info, Config>,
 
-    #[account(mut)]
-    staker: Signer<'info>,
-
     #[account(
         mut,
         seeds = ["staker".as_bytes().as_ref(), staker.key().as_ref()],
         bump,
-        //has_one = staker,
-        close = staker,
     )]
     stake_account: Account<'info, StakeAccount>,
 
-    //#[account(
-    //    seeds = ["staking-pool".as_bytes().as_ref(), config.key().as_ref()],
-    //    bump,
-    //)]
-    #[account(mut)]
-    staking_pool: Account<'info, TokenAccount>,
-
+    #[account(
+        mut,
+        constraint = staker_token_account.owner == staker.key(),
+        constraint = staker_token_account.mint == mint.key()
+    )]
+    staker_token_account: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    staking_pool: Account<'info, TokenAccount>,
+
+    staker: Signer<'info>,
+    mint: Account<'info, Mint>,
+    token_program: Program<'info, Token>,
+}
+
+
+#[derive(Accounts)]
+pub struct RequestUnstake<'info> {
+    #[account(
+        mut,
+        seeds = ["config".as_bytes().as_ref()],
+        bump = config.bump,
+    )]
+    config: Account<'info, Config>,
+
+    staker: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = ["staker".as_bytes().as_ref(), staker.key().as_ref()],
+        bump,
+    )]
+    stake_account: Account<'info, StakeAccount>,
+}
+
+
+#[derive(Accounts)]
+pub struct CompleteUnstake<'info> {
+    #[account(
+        seeds = ["config".as_bytes().as_ref()],
+        bump = config.bump,
+        has_one = staking_pool
+    )]
+    config: Account<'info, Config>,
+
+    #[account(mut)]
+    staker: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = ["staker".as_bytes().as_ref(), staker.key().as_ref()],
+        bump,
+        //has_one = staker,
+    )]
+    stake_account: Account<'info, StakeAccount>,
+
+    //#[account(
+    //    seeds = ["staking-pool".as_bytes().as_ref(), config.key().as_ref()],
+    //    bump,
+    //)]
+    #[account(mut)]
+    staking_pool: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = staker_token_account.mint == staking_pool.mint,
+        constraint = staker_token_account.owner == staker.key(),
+    )]
+    staker_token_account: Account<'info, TokenAccount>,
+    token_program: Program<'info, Token>
+}
+
+
+#[derive(Accounts)]
+pub struct FundRewards<'info> {
+    #[account(
+        mut,
+        seeds = ["config".as_bytes().as_ref()],
+        bump = config.bump,
+        has_one = admin @ErrorCode::Unauthorized,
+        has_one = staking_pool,
+    )]
+    config: Account<'info, Config>,
+    admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = ["treasury".as_bytes().as_ref(), config.key().as_ref(), config.native_token_mint.as_ref()],
+        bump,
+    )]
+    treasury: Account<'info, TokenAccount>,
+    #[account(mut)]
+    staking_pool: Account<'info, TokenAccount>,
+    token_program: Program<'info, Token>
+}
+
+#[derive(Accounts)]
+pub struct ClaimRewards<'info> {
+    #[account(
+        seeds = ["config".as_bytes().as_ref()],
+        bump = config.bump,
+        has_one = staking_pool
+    )]
+    config: Account<'info, Config>,
+
+    staker: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = ["staker".as_bytes().as_ref(), staker.key().as_ref()],
+        bump,
+    )]
+    stake_account: Account<'info, StakeAccount>,
+
+    #[account(mut)]
+    staking_pool: Account<'info, TokenAccount>,
+
     #[account(
         mut,
         constraint = staker_token_account.mint == staking_pool.mint,
@@ -936,11 +1865,104 @@ pub struct Unstake<'info> {
     token_program: Program<'info, Token>
 }
 
+#[derive(Accounts)]
+pub struct DelegateStake<'info> {
+    #[account(
+        init,
+        payer = delegator,
+        space = 8 + Delegation::SIZE,
+        seeds = ["delegation".as_bytes().as_ref(), delegator.key().as_ref()],
+        bump
+    )]
+    delegation: Account<'info, Delegation>,
+
+    #[account(mut)]
+    delegator: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = ["staker".as_bytes().as_ref(), delegator.key().as_ref()],
+        bump,
+    )]
+    stake_account: Account<'info, StakeAccount>,
+
+    /// CHECK: only used to derive the delegate's stake account
+    #[account(constraint = delegate.key() != delegator.key() @ErrorCode::InvalidDelegate)]
+    delegate: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = ["staker".as_bytes().as_ref(), delegate.key().as_ref()],
+        bump,
+    )]
+    delegate_stake_account: Account<'info, StakeAccount>,
+    system_program: Program<'info, System>
+}
+
+#[derive(Accounts)]
+pub struct RevokeDelegation<'info> {
+    #[account(
+        mut,
+        seeds = ["delegation".as_bytes().as_ref(), delegator.key().as_ref()],
+        bump = delegation.bump,
+        has_one = delegator,
+        has_one = delegate,
+        close = delegator
+    )]
+    delegation: Account<'info, Delegation>,
+
+    #[account(mut)]
+    delegator: Signer<'info>,
+
+    #[account(
+        mut,
+        seeds = ["staker".as_bytes().as_ref(), delegator.key().as_ref()],
+        bump,
+    )]
+    stake_account: Account<'info, StakeAccount>,
+
+    /// CHECK: checked against the delegation
+    delegate: UncheckedAccount<'info>,
+
+    #[account(
+        mut,
+        seeds = ["staker".as_bytes().as_ref(), delegate.key().as_ref()],
+        bump,
+    )]
+    delegate_stake_account: Account<'info, StakeAccount>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateRoundVote<'info> {
+    #[account(
+        seeds = ["config".as_bytes().as_ref()],
+        bump = config.bump
+    )]
+    config: Account<'info, Config>,
+    /// CHECK: the handler only accepts the legacy RoundVote layout
+    #[account(mut, owner = crate::ID)]
+    round_votes: UncheckedAccount<'info>,
+    #[account(mut)]
+    payer: Signer<'info>,
+    system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateNextRoundVoter<'info> {
+    /// CHECK: the handler only accepts the legacy NextRoundVoter layout
+    #[account(mut, owner = crate::ID)]
+    voter_account: UncheckedAccount<'info>,
+    #[account(mut)]
+    payer: Signer<'info>,
+    system_program: Program<'info, System>,
+}
 
 #[account]
 pub struct Campaign {
     // The user starting a campaign
     fundstarter: Pubkey,
+    // Position among the fundstarter's campaigns, part of the campaign seeds
+    index: u64,
     // The wallet that'll receive the tokens
     vault: Pubkey,
     // The campaign description, should not take > 200 bytes of storage
@@ -962,12 +1984,22 @@ pub struct Campaign {
     active_round: u8,
     // Current round account
     active_round_address: Pubkey,
+    start_time: i64,
+    // moderation closes after this even if the campaign is still running
+    moderation_end_time: i64,
 
-    is_valid_votes: u8,
-    not_valid_votes: u8,
+    // moderation tallies, in basis points of voting power
+    is_valid_votes: u64,
+    not_valid_votes: u64,
     // the number of moderators that have exercised their voting right so far
     moderator_votes: u64,
     is_valid_campaign: bool,
+    // What happens to donations when the campaign falls short
+    funding_mode: u8,
+    // Round votes are committed as hashes and revealed after voting closes
+    commit_reveal: bool,
+    // Donators vote with the square root of their donation
+    quadratic_voting: bool,
     // Bump of campaign PDA
     bump: u8,
 }
@@ -977,14 +2009,108 @@ const CID_SIZE: usize = 50;
 const PUBKEY_SIZE: usize = 32;
 const U8_SIZE: usize = 1;
 const U64_SIZE: usize = 8;
+const U128_SIZE: usize = 16;
 const BOOL_SIZE: usize = 1;
 
 impl Campaign {
-    const SIZE: usize = (PUBKEY_SIZE * 4) + (U8_SIZE * 6)
-        +(U64_SIZE * 3)        
+    const SIZE: usize = (PUBKEY_SIZE * 4) + (U8_SIZE * 5)
+        +(U64_SIZE * 8)
         +(4 + MAX_DESCRIPTION_SIZE)
         +(4 + CID_SIZE)
-        +(BOOL_SIZE * 2);
+        +(BOOL_SIZE * 4);
+
+    // No more rounds, votes or moderation can change the outcome
+    fn is_concluded(&self) -> bool {
+        self.status != CampaignStatus::CampaignActive.to_u8()
+            && self.status != CampaignStatus::CampaignTargetMet.to_u8()
+    }
+
+    // Moderation closes as soon as it can be released, so outcomes that get slashed are final
+    fn moderation_closed(&self, current_time: i64) -> bool {
+        self.is_concluded() || current_time > self.moderation_end_time
+    }
+
+    // Raised everything it ever will, whatever is left in the vault is owed to the fundstarter
+    fn stopped_raising(&self) -> bool {
+        self.status == CampaignStatus::CampaignEnded.to_u8()
+            || self.status == CampaignStatus::CampaignPartiallyFunded.to_u8()
+    }
+
+    fn is_final_round(&self, round: &Round) -> bool {
+        round.round == self.total_rounds
+    }
+
+    // A flexible campaign that missed a deadline releases the partial raise
+    fn is_partial_raise(&self, round: &Round) -> bool {
+        round.status == RoundStatus::RoundFailed.to_u8()
+            && self.status == CampaignStatus::CampaignPartiallyFunded.to_u8()
+    }
+
+    // Funds stay escrowed until voters approve the round. The final round
+    // has no vote after it, so it's released once its target is met.
+    fn releases(&self, round: &Round) -> bool {
+        round.status == RoundStatus::RoundEnded.to_u8()
+            || (self.is_final_round(round) && round.status == RoundStatus::RoundTargetMet.to_u8())
+            || self.is_partial_raise(round)
+    }
+
+    fn end_round_vote(&mut self, round: &mut Round, terminated: bool) -> Result<()> {
+        require!(!self.is_concluded(), ErrorCode::CampaignInactive);
+        if terminated {
+            self.can_start_next_round = false;
+            self.status = match FundingMode::from(self.funding_mode)? {
+                // Nothing was paid out yet, so every donor gets refunded
+                FundingMode::AllOrNothing => CampaignStatus::CampaignFailed.to_u8(),
+                // Approved rounds stay with the fundstarter, donors of this round get refunded
+                FundingMode::Flexible => CampaignStatus::CampaignRefunding.to_u8(),
+            };
+            round.status = RoundStatus::RoundTerminated.to_u8();
+        } else {
+            round.status = RoundStatus::RoundEnded.to_u8();
+        }
+        Ok(())
+    }
+
+    fn refund_for(&self, round_key: Pubkey, round: &Round, contribution: u64, vault_amount: u64) -> Result<u64> {
+        match CampaignStatus::from(self.status)? {
+            // Only the terminated round is refunded, in full
+            CampaignStatus::CampaignRefunding => {
+                require!(
+                    self.active_round_address == round_key &&
+                        round.status == RoundStatus::RoundTerminated.to_u8(),
+                    ErrorCode::RefundsUnavailable
+                );
+                Ok(contribution)
+            }
+            // Every donor gets a pro-rata share of the funds left in the vault,
+            // balance being the contributions not yet refunded
+            CampaignStatus::CampaignFrozen | CampaignStatus::CampaignFailed => {
+                Ok((contribution as u128)
+                    .checked_mul(vault_amount as u128)
+                    .unwrap()
+                    .checked_div(self.balance as u128)
+                    .unwrap() as u64)
+            }
+            _ => err!(ErrorCode::RefundsUnavailable),
+        }
+    }
+
+    fn record_refund(&mut self, round: &mut Round, contribution: u64) {
+        round.balance = round.balance.checked_sub(contribution).unwrap();
+        round.update_sqrt_donations(contribution, 0);
+        self.balance = self.balance.checked_sub(contribution).unwrap();
+    }
+}
+
+// Tracks how many campaigns a fundstarter has started
+#[account]
+pub struct CreatorProfile {
+    campaign_count: u64,
+    bump: u8,
+}
+
+impl CreatorProfile {
+    const SIZE: usize = U64_SIZE + U8_SIZE;
 }
 
 #[account]
@@ -1001,64 +2127,353 @@ pub struct Round {
     donators: u64,
     // Status
     status: u8,
+    // amount the fundstarter has withdrawn from this round
+    withdrawn: u64,
+    start_time: i64,
+    // donations close after this time, 0 if the round has no deadline
+    deadline: i64,
+    // sum of the square roots of each donator's total, quadratic voting weighs donators against it
+    sqrt_donations: u64,
+}
+
+impl Round {
+    const SIZE: usize = 32 + 1 + 8 + 8 + 8 + 1 + 8 + 8 + 8 + 8;
+
+    fn deadline_passed(&self, current_time: i64) -> bool {
+        self.deadline != 0 && current_time > self.deadline
+    }
+
+    fn update_sqrt_donations(&mut self, previous_amount: u64, amount: u64) {
+        self.sqrt_donations = self.sqrt_donations
+            .checked_sub(isqrt(previous_amount)).unwrap()
+            .checked_add(isqrt(amount)).unwrap();
+    }
+}
+
+
+#[account]
+pub struct RoundVote {
+    // continue campaign votes, in basis points of voting power
+    continue_campaign: u64,
+    // terminate campaign votes, in basis points of voting power
+    terminate_campaign: u64,
+    
+    // number of donators that voted
+    donators_voted: u64,
+    // number of stakers that voted
+    stakers_voted: u64,
+
+    start_time: i64,
+    // fixed when voting opens, so config changes don't affect an ongoing ballot
+    end_time: i64,
+    // end of the reveal window in commit-reveal campaigns, end_time otherwise
+    reveal_end_time: i64,
+    voting_ended: bool,
+
+    // staking totals when voting opened, staker weight and quorum use these
+    total_amount_staked: u64,
+    active_stakers: u64,
+}
+
+impl RoundVote {
+    const SIZE: usize = 8 + 8 + 8 + 8 + 8 + 8 + 8 + 1 + 8 + 8;
+
+    fn is_open(&self, current_time: i64) -> bool {
+        current_time >= self.start_time && current_time <= self.end_time
+    }
+
+    fn is_revealing(&self, current_time: i64) -> bool {
+        current_time > self.end_time && current_time <= self.reveal_end_time
+    }
+
+    // Terminating takes a majority of the voting power and a quorum of the eligible voters
+    fn terminates_campaign(&self, round_donators: u64, minimum_vote_percentage: u8) -> bool {
+        let maximum_possible_voters = self.active_stakers
+            .checked_add(round_donators)
+            .unwrap();
+        let voters_this_round = self.stakers_voted
+            .checked_add(self.donators_voted)
+            .unwrap();
+
+        self.terminate_campaign > self.continue_campaign
+            && quorum_reached(voters_this_round, maximum_possible_voters, minimum_vote_percentage)
+    }
+
+    fn tally_for(&mut self, continue_campaign: bool) -> &mut u64 {
+        if continue_campaign {
+            &mut self.continue_campaign
+        } else {
+            &mut self.terminate_campaign
+        }
+    }
+
+    // Voters can switch sides until voting ends, their weight moves to the new tally
+    // and they are only counted towards quorum once
+    fn cast(&mut self, voter: &mut NextRoundVoter, continue_campaign: bool) -> Result<()> {
+        if voter.has_voted {
+            let previous = voter.choice.ok_or(ErrorCode::BallotNotRecorded)?;
+            let tally = self.tally_for(previous);
+            *tally = tally.checked_sub(voter.voting_power).unwrap();
+        } else {
+            match VoterType::from(voter.voter_type)? {
+                VoterType::Donator => {
+                    self.donators_voted = self.donators_voted.checked_add(1).unwrap();
+                },
+                VoterType::Staker => {
+                    self.stakers_voted = self.stakers_voted.checked_add(1).unwrap();
+                }
+            }
+        }
+
+        let tally = self.tally_for(continue_campaign);
+        *tally = tally.checked_add(voter.voting_power).unwrap();
+        voter.has_voted = true;
+        voter.choice = Some(continue_campaign);
+
+        Ok(())
+    }
 }
 
-impl Round {
-    const SIZE: usize = 32 + 1 + 8 + 8 + 8 + 1;
-}
+// RoundVote as stored before tallies were widened to basis points
+#[derive(AnchorDeserialize)]
+pub struct LegacyRoundVote {
+    continue_campaign: u8,
+    terminate_campaign: u8,
+    donators_voted: u64,
+    stakers_voted: u64,
+    start_time: i64,
+    voting_ended: bool,
+}
+
+impl LegacyRoundVote {
+    const SIZE: usize = 1 + 1 + 8 + 8 + 8 + 1;
+}
+
+#[account]
+pub struct Donator {
+    // total donated this round, net of fees
+    amount: u64,
+    round: u8,
+    // number of donations made this round
+    donation_count: u64,
+    bump: u8
+}
+
+impl Donator {
+    const SIZE: usize = 8 + 1 + 8 + 1;
+}
+
+#[account]
+pub struct StakeAccount {
+    // deposit-weighted average of when the stake was deposited
+    stake_time: i64,
+    // when tokens were last added, later stake can't vote in earlier snapshots
+    last_deposit_time: i64,
+    deposit: u64,
+    // rewards settled but not claimed yet
+    reward: u64,
+    // deposit * Config.reward_per_token at the last settlement, unscaled
+    reward_debt: u128,
+    // end of the latest round vote this stake took part in
+    locked_until: i64,
+    // moderations taken part in and not released yet
+    active_moderations: u64,
+    // latest moderation_end_time among the campaigns moderated, see `inherit_locks`
+    moderations_close_at: i64,
+    // requested for withdrawal, no longer part of the deposit
+    pending_unstake: u64,
+    unstake_available_at: i64,
+    // chosen at stake time, can't be unstaked before it
+    lock_end: i64,
+    // lent to another staker, see Delegation
+    delegated: bool,
+    // deposits other stakers delegated to this one
+    delegated_stake: u64,
+    // when this stake's own delegation was last revoked
+    last_revocation_time: i64,
+}
+
+impl StakeAccount {
+    const SIZE: usize = 8 + 8 + 8 + 8 + 16 + 8 + 8 + 8 + 8 + 8 + 1 + 8 + 8 + 8;
+
+    // Whether a round vote or a moderation still counts this stake
+    fn backs_votes(&self, current_time: i64) -> bool {
+        current_time <= self.locked_until || self.active_moderations > 0
+    }
+
+    // Held until `release_moderation`, which opens once the campaign's moderation closes
+    fn add_moderation(&mut self, moderation_end_time: i64) {
+        self.active_moderations = self.active_moderations.checked_add(1).unwrap();
+        self.moderations_close_at = self.moderations_close_at.max(moderation_end_time);
+    }
+
+    fn release_moderation(&mut self) {
+        self.active_moderations = self.active_moderations.checked_sub(1).unwrap();
+    }
+
+    // The delegate may have cast votes opened before a revocation with this stake
+    fn undelegated_since(&self, opened_at: i64) -> bool {
+        opened_at > self.last_revocation_time
+    }
+
+    // A revoked stake may still back the delegate's votes and moderations,
+    // it stays locked until they end and the moderations it could have joined have closed
+    fn inherit_locks(&mut self, delegate: &StakeAccount) {
+        self.locked_until = self.locked_until
+            .max(delegate.locked_until)
+            .max(delegate.moderations_close_at);
+    }
+
+    fn is_unlocked(&self, current_time: i64) -> bool {
+        !self.backs_votes(current_time) && current_time >= self.lock_end
+    }
+
+    // Weight of the deposit at `at` in basis points, the lock boost decays as the lock runs out
+    // while the age boost grows until the stake is STAKE_AGE_FOR_FULL_BOOST_IN_SECONDS old
+    fn boost(&self, at: i64) -> u64 {
+        let remaining_lock = (self.lock_end - at).clamp(0, MAX_STAKE_LOCK_IN_SECONDS as i64) as u64;
+        let age = (at - self.stake_time).clamp(0, STAKE_AGE_FOR_FULL_BOOST_IN_SECONDS as i64) as u64;
+
+        MAX_BASIS_POINTS
+            + MAX_LOCK_BOOST_BASIS_POINTS * remaining_lock / MAX_STAKE_LOCK_IN_SECONDS
+            + MAX_AGE_BOOST_BASIS_POINTS * age / STAKE_AGE_FOR_FULL_BOOST_IN_SECONDS
+    }
+
+    // Adds the rewards accrued since the last settlement, must run before the deposit changes
+    // Rounds down, so the pool never owes more than it was funded with
+    fn settle_rewards(&mut self, reward_per_token: u128) {
+        let accrued = (self.deposit as u128)
+            .checked_mul(reward_per_token).unwrap();
+        let pending = accrued.checked_sub(self.reward_debt).unwrap() / REWARD_PRECISION;
+        self.reward = self.reward.checked_add(pending as u64).unwrap();
+        self.reward_debt = accrued;
+    }
+
+    fn update_reward_debt(&mut self, reward_per_token: u128) {
+        self.reward_debt = (self.deposit as u128)
+            .checked_mul(reward_per_token).unwrap();
+    }
+
+    // Locks only ever get longer
+    fn extend_lock(&mut self, lock_end: i64) {
+        self.lock_end = self.lock_end.max(lock_end);
+    }
+
+    // Tokens added later count as younger stake, so stake age is averaged by amount
+    fn add_deposit(&mut self, amount: u64, current_time: i64) {
+        let total = (self.deposit as u128).checked_add(amount as u128).unwrap();
+        let weighted_time = (self.stake_time as i128) * (self.deposit as i128)
+            + (current_time as i128) * (amount as i128);
+
+        self.stake_time = (weighted_time / total as i128) as i64;
+        self.last_deposit_time = current_time;
+        self.deposit = self.deposit.checked_add(amount).unwrap();
+    }
+
+    // Returns true once the whole deposit has been withdrawn
+    fn withdraw(&mut self, amount: u64) -> Result<bool> {
+        require!(amount > 0 && amount <= self.deposit, ErrorCode::InvalidStakeAmount);
+        self.deposit -= amount;
+
+        Ok(self.deposit == 0)
+    }
+
+    // Further requests add to the pending amount and restart the cooldown
+    fn request_unstake(&mut self, amount: u64, available_at: i64) -> Result<bool> {
+        let emptied = self.withdraw(amount)?;
+        self.pending_unstake = self.pending_unstake.checked_add(amount).unwrap();
+        self.unstake_available_at = available_at;
+
+        Ok(emptied)
+    }
+
+    fn take_pending_unstake(&mut self, current_time: i64) -> Result<u64> {
+        require!(self.pending_unstake > 0, ErrorCode::NoPendingUnstake);
+        require!(current_time >= self.unstake_available_at, ErrorCode::UnstakeCooldownActive);
+
+        Ok(std::mem::take(&mut self.pending_unstake))
+    }
 
+    // Returns what to pay out and whether the account is emptied and should be closed.
+    // Rewards can't be claimed once the account is closed, pay them out with the deposit
+    // and clear them, so they can't be claimed again should the account be revived
+    fn complete_unstake(&mut self, current_time: i64) -> Result<(u64, bool)> {
+        let amount = self.take_pending_unstake(current_time)?;
+        if self.deposit > 0 {
+            return Ok((amount, false));
+        }
 
-#[account]
-pub struct RoundVote {
-    // continue campaign votes
-    continue_campaign: u8,
-    // terminate campaign votes
-    terminate_campaign: u8,
-    
-    // number of donators that voted
-    donators_voted: u64,
-    // number of stakers that voted
-    stakers_voted: u64,
+        require!(self.is_unlocked(current_time), ErrorCode::StakeLocked);
+        Ok((amount.checked_add(std::mem::take(&mut self.reward)).unwrap(), true))
+    }
 
-    start_time: i64,
-    voting_ended: bool,
-}
+    fn claim_rewards(&mut self, reward_per_token: u128) -> Result<u64> {
+        self.settle_rewards(reward_per_token);
+        self.update_reward_debt(reward_per_token);
+        require!(self.reward > 0, ErrorCode::NoRewardsToClaim);
 
-impl RoundVote {
-    const SIZE: usize = 1 + 1 + 8 + 8 + 8 + 1;
+        Ok(std::mem::take(&mut self.reward))
+    }
 }
 
+// One per delegator, the delegated amount is fixed when delegating
 #[account]
-pub struct Donator {
+pub struct Delegation {
+    delegator: Pubkey,
+    delegate: Pubkey,
     amount: u64,
-    round: u8,
-    bump: u8
+    delegated_at: i64,
+    bump: u8,
 }
 
-impl Donator {
-    const SIZE: usize = 8 + 1 + 1;
+impl Delegation {
+    const SIZE: usize = PUBKEY_SIZE + PUBKEY_SIZE + U64_SIZE + U64_SIZE + U8_SIZE;
+
+    // Like the delegate's own deposit, stake delegated after a vote opened isn't part of its snapshot
+    fn counts_at(&self, opened_at: i64) -> bool {
+        self.delegated_at <= opened_at
+    }
 }
 
 #[account]
-pub struct StakeAccount {
-    stake_time: i64,
-    deposit: u64,
-    reward: u64,
+pub struct NextRoundVoter {
+    // basis points of the whole vote, 10_000 being all of it
+    voting_power: u64,
+    // counted towards quorum, kept apart from `choice` since migrated ballots voted without recording it
+    has_voted: bool,
+    // true to continue the campaign, None before voting and for ballots migrated by migrate_next_round_voter
+    choice: Option<bool>,
+    // ballot_commitment of a ballot not revealed yet, commit-reveal campaigns only
+    commitment: Option<[u8; 32]>,
+    voter_type: u8,
+    bump: u8
 }
 
-impl StakeAccount {
-    const SIZE: usize = 8 + 8 + 8;
+impl NextRoundVoter {
+    const SIZE: usize = 8 + 1 + 2 + (1 + 32) + 1 + 1;
+
+    // Clears the commitment, so each committed ballot is revealed once
+    fn reveal(&mut self, voter: &Pubkey, round_votes: &Pubkey, continue_campaign: bool, salt: &[u8; 32]) -> Result<()> {
+        require!(
+            self.commitment == Some(ballot_commitment(voter, round_votes, continue_campaign, salt)),
+            ErrorCode::InvalidReveal
+        );
+        self.commitment = None;
+
+        Ok(())
+    }
 }
 
-#[account]
-pub struct NextRoundVoter {
+// NextRoundVoter as stored before voting power was widened to basis points
+#[derive(AnchorDeserialize)]
+pub struct LegacyNextRoundVoter {
     voting_power: u8,
     has_voted: bool,
     voter_type: u8,
     bump: u8
 }
 
-impl NextRoundVoter {
+impl LegacyNextRoundVoter {
     const SIZE: usize = 1 + 1 + 1 + 1;
 }
 
@@ -1090,13 +2505,21 @@ impl VoterType {
 
 #[account]
 pub struct Moderator {
-    voting_power: u8,
+    // basis points of the whole moderation vote
+    voting_power: u64,
     has_voted: bool,
+    // the vote cast, only meaningful once has_voted is set
+    thumbs_up: bool,
     moderator_type: u8,
 }
 
 impl Moderator {
-    const SIZE: usize = 1 + 1 + 1;
+    const SIZE: usize = 8 + 1 + 1 + 1;
+
+    // Voted for the outcome the campaign didn't end up with
+    fn voted_against(&self, campaign_frozen: bool) -> bool {
+        self.has_voted && self.thumbs_up == campaign_frozen
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, AnchorDeserialize, AnchorSerialize)]
@@ -1128,6 +2551,10 @@ pub enum RoundStatus {
     DonationsOpen,
     RoundTargetMet,
     RoundEnded,
+    // Voters decided the campaign shouldn't continue
+    RoundTerminated,
+    // Deadline passed before the target was met
+    RoundFailed,
 }
 
 impl RoundStatus {
@@ -1136,6 +2563,8 @@ impl RoundStatus {
             1 => Ok(RoundStatus::DonationsOpen),
             2 => Ok(RoundStatus::RoundTargetMet),
             3 => Ok(RoundStatus::RoundEnded),
+            4 => Ok(RoundStatus::RoundTerminated),
+            5 => Ok(RoundStatus::RoundFailed),
             invalid_number => {
                 msg!("Invalid state: {}", invalid_number);
                 Err(ErrorCode::InvalidStatus.into())
@@ -1148,6 +2577,8 @@ impl RoundStatus {
             RoundStatus::DonationsOpen => 1,
             RoundStatus::RoundTargetMet => 2,
             RoundStatus::RoundEnded => 3,
+            RoundStatus::RoundTerminated => 4,
+            RoundStatus::RoundFailed => 5,
         }
     }
 }
@@ -1157,6 +2588,14 @@ pub enum CampaignStatus {
     CampaignActive,
     CampaignTargetMet,
     CampaignEnded,
+    // Flexible campaign terminated by vote, donors of the active round can claim refunds
+    CampaignRefunding,
+    // Flagged as fraudulent by moderators, donors of every round can claim refunds
+    CampaignFrozen,
+    // All-or-nothing campaign that fell short, donors of every round can claim refunds
+    CampaignFailed,
+    // Flexible campaign that fell short, the fundstarter keeps the partial raise
+    CampaignPartiallyFunded,
 }
 
 impl CampaignStatus {
@@ -1165,6 +2604,10 @@ impl CampaignStatus {
             1 => Ok(CampaignStatus::CampaignActive),
             2 => Ok(CampaignStatus::CampaignTargetMet),
             3 => Ok(CampaignStatus::CampaignEnded),
+            4 => Ok(CampaignStatus::CampaignRefunding),
+            5 => Ok(CampaignStatus::CampaignFrozen),
+            6 => Ok(CampaignStatus::CampaignFailed),
+            7 => Ok(CampaignStatus::CampaignPartiallyFunded),
             invalid_number => {
                 msg!("Invalid state: {}", invalid_number);
                 Err(ErrorCode::InvalidStatus.into())
@@ -1177,6 +2620,38 @@ impl CampaignStatus {
             CampaignStatus::CampaignActive => 1,
             CampaignStatus::CampaignTargetMet => 2,
             CampaignStatus::CampaignEnded => 3,
+            CampaignStatus::CampaignRefunding => 4,
+            CampaignStatus::CampaignFrozen => 5,
+            CampaignStatus::CampaignFailed => 6,
+            CampaignStatus::CampaignPartiallyFunded => 7,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, AnchorDeserialize, AnchorSerialize)]
+pub enum FundingMode {
+    // Donors are refunded unless the campaign target is met
+    AllOrNothing,
+    // The fundstarter keeps whatever was raised
+    Flexible,
+}
+
+impl FundingMode {
+    fn from(val: u8) -> std::result::Result<FundingMode, Error> {
+        match val {
+            1 => Ok(FundingMode::AllOrNothing),
+            2 => Ok(FundingMode::Flexible),
+            invalid_number => {
+                msg!("Invalid funding mode: {}", invalid_number);
+                Err(ErrorCode::InvalidFundingMode.into())
+            }
+        }
+    }
+
+    fn to_u8(&self) -> u8 {
+        match self {
+            FundingMode::AllOrNothing => 1,
+            FundingMode::Flexible => 2,
         }
     }
 }
@@ -1185,24 +2660,250 @@ impl CampaignStatus {
 #[derive(Default)]
 pub struct Config {
     admin: Pubkey,
+    // Set by the admin, becomes admin once it accepts
+    pending_admin: Pubkey,
     native_token_mint: Pubkey,
     donation_fee: u64,
     staking_initialized: bool,   
     active_stakers: u64,
     total_amount_staked: u64,
     round_voting_period_in_days: u8,
+    // quorum for round continuation votes
     minimum_required_vote_percentage: u8,
+    // quorum for moderation votes
+    minimum_moderation_vote_percentage: u8,
     donator_voting_rights: u8,
     staker_voting_rights: u8,
     staker_moderation_rights: u8,
+    // time between requesting an unstake and withdrawing the tokens
+    unstake_cooldown_in_seconds: u64,
+    // share of their deposit moderators lose for voting against the outcome, 0 disables slashing
+    moderation_slash_percentage: u8,
     staking_pool: Pubkey,
+    // staking rewards per staked token, scaled by REWARD_PRECISION
+    reward_per_token: u128,
     bump: u8,
 }
 
 impl Config {
-    const SIZE: usize = (3 * PUBKEY_SIZE) + (3 * U64_SIZE)
-        +(6 * U8_SIZE) + (1 * BOOL_SIZE);
+    const SIZE: usize = (4 * PUBKEY_SIZE) + (4 * U64_SIZE)
+        +(8 * U8_SIZE) + (1 * BOOL_SIZE) + (1 * U128_SIZE);
     //const SIZE: usize = 2000;
+
+    // The fee stays below the amount since donation_fee is capped below MAX_BASIS_POINTS
+    fn donation_fee_for(&self, amount: u64) -> u64 {
+        (amount as u128)
+            .checked_mul(self.donation_fee as u128)
+            .unwrap()
+            .checked_div(MAX_BASIS_POINTS as u128)
+            .unwrap() as u64
+    }
+
+    fn record_stake(&mut self, amount: u64, new_staker: bool) {
+        if new_staker {
+            self.active_stakers = self.active_stakers
+                .checked_add(1).unwrap();
+        }
+        self.total_amount_staked = self.total_amount_staked
+            .checked_add(amount).unwrap();
+    }
+
+    fn open_stake(&mut self, stake_account: &mut StakeAccount, amount: u64, current_time: i64, lock_end: i64) {
+        stake_account.stake_time = current_time;
+        stake_account.last_deposit_time = current_time;
+        stake_account.deposit = amount;
+        stake_account.reward = 0;
+        stake_account.update_reward_debt(self.reward_per_token);
+        stake_account.locked_until = 0;
+        stake_account.active_moderations = 0;
+        stake_account.pending_unstake = 0;
+        stake_account.unstake_available_at = 0;
+        stake_account.lock_end = lock_end;
+        stake_account.delegated = false;
+        stake_account.delegated_stake = 0;
+        stake_account.moderations_close_at = 0;
+        stake_account.last_revocation_time = 0;
+
+        self.record_stake(amount, true);
+    }
+
+    fn add_stake(&mut self, stake_account: &mut StakeAccount, amount: u64, current_time: i64) {
+        // A staker whose whole deposit is cooling down counts as active again
+        let rejoining = stake_account.deposit == 0;
+        stake_account.settle_rewards(self.reward_per_token);
+        stake_account.add_deposit(amount, current_time);
+        stake_account.update_reward_debt(self.reward_per_token);
+
+        self.record_stake(amount, rejoining);
+    }
+
+    fn request_unstake(&mut self, stake_account: &mut StakeAccount, amount: u64, current_time: i64) -> Result<()> {
+        require!(stake_account.is_unlocked(current_time), ErrorCode::StakeLocked);
+        require!(!stake_account.delegated, ErrorCode::StakeDelegated);
+        // The account closes once emptied, delegators must be able to revoke from it first
+        require!(
+            amount != stake_account.deposit || stake_account.delegated_stake == 0,
+            ErrorCode::HoldsDelegatedStake
+        );
+
+        let available_at = current_time
+            .checked_add(self.unstake_cooldown_in_seconds as i64)
+            .unwrap();
+        stake_account.settle_rewards(self.reward_per_token);
+        let emptied = stake_account.request_unstake(amount, available_at)?;
+        stake_account.update_reward_debt(self.reward_per_token);
+
+        self.record_unstake(amount, emptied);
+        Ok(())
+    }
+
+    // Unstake requests are refused while the moderation is unreleased,
+    // so the deposit slashed here still holds everything that backed it
+    fn release_moderation(&mut self, stake_account: &mut StakeAccount, slashed: bool) -> Result<u64> {
+        stake_account.release_moderation();
+        if !slashed {
+            return Ok(0);
+        }
+
+        // The slash stays below the deposit, so the staker remains active
+        let slash = self.moderation_slash_for(stake_account.deposit);
+        stake_account.settle_rewards(self.reward_per_token);
+        stake_account.withdraw(slash)?;
+        stake_account.update_reward_debt(self.reward_per_token);
+        self.record_unstake(slash, false);
+        Ok(slash)
+    }
+
+    fn distribute_rewards(&mut self, amount: u64) {
+        let per_token = (amount as u128)
+            .checked_mul(REWARD_PRECISION).unwrap()
+            / (self.total_amount_staked as u128);
+        self.reward_per_token = self.reward_per_token
+            .checked_add(per_token).unwrap();
+    }
+
+    fn record_unstake(&mut self, amount: u64, staker_left: bool) {
+        if staker_left {
+            self.active_stakers = self.active_stakers
+                .checked_sub(1).unwrap();
+        }
+        self.total_amount_staked = self.total_amount_staked
+            .checked_sub(amount).unwrap();
+    }
+
+    fn round_voting_period(&self) -> i64 {
+        (self.round_voting_period_in_days as i64)
+            .checked_mul(DAY_IN_SECONDS as i64)
+            .unwrap()
+    }
+
+    fn moderation_slash_for(&self, deposit: u64) -> u64 {
+        (deposit as u128)
+            .checked_mul(self.moderation_slash_percentage as u128)
+            .unwrap()
+            .checked_div(100)
+            .unwrap() as u64
+    }
+
+    fn governance_params(&self) -> GovernanceParams {
+        GovernanceParams {
+            round_voting_period_in_days: self.round_voting_period_in_days,
+            minimum_required_vote_percentage: self.minimum_required_vote_percentage,
+            minimum_moderation_vote_percentage: self.minimum_moderation_vote_percentage,
+            donator_voting_rights: self.donator_voting_rights,
+            staker_voting_rights: self.staker_voting_rights,
+            staker_moderation_rights: self.staker_moderation_rights,
+            donation_fee: self.donation_fee,
+            unstake_cooldown_in_seconds: self.unstake_cooldown_in_seconds,
+            moderation_slash_percentage: self.moderation_slash_percentage,
+        }
+    }
+
+    fn set_governance_params(&mut self, params: &GovernanceParams) {
+        self.round_voting_period_in_days = params.round_voting_period_in_days;
+        self.minimum_required_vote_percentage = params.minimum_required_vote_percentage;
+        self.minimum_moderation_vote_percentage = params.minimum_moderation_vote_percentage;
+        self.donator_voting_rights = params.donator_voting_rights;
+        self.staker_voting_rights = params.staker_voting_rights;
+        self.staker_moderation_rights = params.staker_moderation_rights;
+        self.donation_fee = params.donation_fee;
+        self.unstake_cooldown_in_seconds = params.unstake_cooldown_in_seconds;
+        self.moderation_slash_percentage = params.moderation_slash_percentage;
+    }
+}
+
+// Config values the admin is allowed to change after initialization
+#[derive(Clone, Copy, PartialEq, AnchorDeserialize, AnchorSerialize)]
+pub struct GovernanceParams {
+    pub round_voting_period_in_days: u8,
+    pub minimum_required_vote_percentage: u8,
+    pub minimum_moderation_vote_percentage: u8,
+    pub donator_voting_rights: u8,
+    pub staker_voting_rights: u8,
+    pub staker_moderation_rights: u8,
+    // Fee charged on each donation, in basis points
+    pub donation_fee: u64,
+    pub unstake_cooldown_in_seconds: u64,
+    pub moderation_slash_percentage: u8,
+}
+
+impl GovernanceParams {
+    fn validate(&self) -> Result<()> {
+        require!(self.round_voting_period_in_days > 0, ErrorCode::InvalidVotingPeriod);
+        require!(
+            self.minimum_required_vote_percentage <= 100,
+            ErrorCode::InvalidVotePercentage
+        );
+        require!(
+            self.minimum_moderation_vote_percentage <= 100,
+            ErrorCode::InvalidVotePercentage
+        );
+        // Donators and stakers share the round votes between them
+        require!(
+            self.donator_voting_rights as u16 + self.staker_voting_rights as u16 == 100,
+            ErrorCode::InvalidVotingRights
+        );
+        require!(
+            self.staker_moderation_rights > 0 && self.staker_moderation_rights <= 100,
+            ErrorCode::InvalidModerationRights
+        );
+        require!(self.donation_fee < MAX_BASIS_POINTS, ErrorCode::InvalidDonationFee);
+        require!(
+            self.unstake_cooldown_in_seconds <= MAX_UNSTAKE_COOLDOWN_IN_SECONDS,
+            ErrorCode::InvalidUnstakeCooldown
+        );
+        require!(
+            self.moderation_slash_percentage <= MAX_MODERATION_SLASH_PERCENTAGE,
+            ErrorCode::InvalidSlashPercentage
+        );
+        Ok(())
+    }
+}
+
+#[event]
+pub struct ConfigUpdated {
+    admin: Pubkey,
+    old: GovernanceParams,
+    new: GovernanceParams,
+}
+
+#[event]
+pub struct AdminProposed {
+    admin: Pubkey,
+    pending_admin: Pubkey,
+}
+
+#[event]
+pub struct AdminTransferred {
+    old_admin: Pubkey,
+    new_admin: Pubkey,
+}
+
+#[event]
+pub struct ModeratorSlashed {
+    campaign: Pubkey,
+    staker: Pubkey,
+    amount: u64,
 }
 
 #[error_code]
@@ -1233,6 +2934,839 @@ pub enum ErrorCode {
     VotingStillActive,
     #[msg("Can't start next round until we tally votes and end the current round")]
     RoundHasntEnded,
+    #[msg("Only the admin can perform this action")]
+    Unauthorized,
+    #[msg("Voting period must be at least one day")]
+    InvalidVotingPeriod,
+    #[msg("Vote percentage can't exceed 100")]
+    InvalidVotePercentage,
+    #[msg("Donator and staker voting rights must add up to 100")]
+    InvalidVotingRights,
+    #[msg("Staker moderation rights must be between 1 and 100")]
+    InvalidModerationRights,
+    #[msg("Donation fee must be below 10000 basis points")]
+    InvalidDonationFee,
+    #[msg("Signer is not the pending admin")]
+    NotPendingAdmin,
+    #[msg("Donation must be greater than 0 after fees")]
+    InvalidDonationAmount,
+    #[msg("Refunds are not available for this round")]
+    RefundsUnavailable,
+    #[msg("Funds of this round are still escrowed")]
+    RoundNotReleased,
+    #[msg("Nothing left to withdraw from this round")]
+    NothingToWithdraw,
+    #[msg("Deadline must be in the future")]
+    InvalidDeadline,
+    #[msg("This round's deadline has passed")]
+    RoundDeadlinePassed,
+    #[msg("This round hasn't expired")]
+    RoundNotExpired,
+    #[msg("Invalid funding mode")]
+    InvalidFundingMode,
+    #[msg("All-or-nothing campaigns can't withdraw before meeting their target")]
+    CampaignTargetNotMet,
+    #[msg("Account is not in the legacy layout")]
+    AccountAlreadyMigrated,
+    #[msg("Voting is not open for this round")]
+    OutsideVotingWindow,
+    #[msg("Stake was deposited after voting opened")]
+    StakedAfterSnapshot,
+    #[msg("Stake is locked until the votes it backs have ended")]
+    StakeLocked,
+    #[msg("Stake amount must be greater than 0 and at most the deposit")]
+    InvalidStakeAmount,
+    #[msg("Reward amount must be greater than 0")]
+    InvalidRewardAmount,
+    #[msg("There are no stakers to reward")]
+    NoStakers,
+    #[msg("No rewards to claim")]
+    NoRewardsToClaim,
+    #[msg("Unstake cooldown can't exceed 30 days")]
+    InvalidUnstakeCooldown,
+    #[msg("No unstake was requested")]
+    NoPendingUnstake,
+    #[msg("Unstake cooldown hasn't ended")]
+    UnstakeCooldownActive,
+    #[msg("Moderation slash can't exceed 50 percent")]
+    InvalidSlashPercentage,
+    #[msg("Campaign is still open to moderation")]
+    ModerationStillActive,
+    #[msg("Stake lock can't exceed 365 days")]
+    InvalidLockDuration,
+    #[msg("Stake is delegated to another staker")]
+    StakeDelegated,
+    #[msg("Delegate must be another staker who isn't delegating or receiving delegations")]
+    InvalidDelegate,
+    #[msg("Stake holding delegations can't be fully unstaked")]
+    HoldsDelegatedStake,
+    #[msg("Ballots cast before choices were recorded can't be changed")]
+    BallotNotRecorded,
+    #[msg("Instruction doesn't match the campaign's voting mode")]
+    WrongVotingMode,
+    #[msg("Ballots can only be revealed between the end of voting and the end of the reveal window")]
+    OutsideRevealWindow,
+    #[msg("Revealed ballot doesn't match the commitment")]
+    InvalidReveal,
+    #[msg("Campaign has concluded, moderation is closed")]
+    ModerationClosed,
+    #[msg("Stake was delegated when this vote opened")]
+    DelegatedWhenOpened,
+    #[msg("Delegation isn't made to this staker or is passed twice")]
+    InvalidDelegation,
 }
 
-// Validate bump seeds
\ No newline at end of file
+// Validate bump seeds
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quorum_requires_more_than_the_percentage() {
+        // 30% of 10 voters is exactly 3, so a 4th voter is needed
+        assert!(!quorum_reached(3, 10, 30));
+        assert!(quorum_reached(4, 10, 30));
+
+        // 30% of 5 voters is 1.5, so 2 voters are enough
+        assert!(!quorum_reached(1, 5, 30));
+        assert!(quorum_reached(2, 5, 30));
+    }
+
+    #[test]
+    fn quorum_follows_the_configured_percentage() {
+        assert!(quorum_reached(6, 10, 50));
+        assert!(!quorum_reached(5, 10, 50));
+        assert!(!quorum_reached(10, 10, 100));
+        assert!(quorum_reached(1, 10, 0));
+        assert!(!quorum_reached(0, 10, 0));
+    }
+
+    #[test]
+    fn quorum_does_not_overflow() {
+        assert!(quorum_reached(u64::MAX, u64::MAX, 99));
+        assert!(!quorum_reached(u64::MAX, u64::MAX, 100));
+    }
+
+    #[test]
+    fn small_holders_keep_their_voting_power() {
+        // 1 token out of 1000 used to truncate to 0
+        assert_eq!(voting_power(1, 1_000, 60), 6);
+        assert_eq!(voting_power(500, 1_000, 60), 3_000);
+        assert_eq!(voting_power(1_000, 1_000, 100), MAX_BASIS_POINTS);
+        assert_eq!(voting_power(u64::MAX as u128, u64::MAX as u128, 100), MAX_BASIS_POINTS);
+        assert_eq!(voting_power(0, 0, 100), 0);
+    }
+
+    #[test]
+    fn isqrt_rounds_down() {
+        for n in 0..10_000u64 {
+            let root = isqrt(n);
+            assert!(root * root <= n && (root + 1) * (root + 1) > n, "isqrt({}) = {}", n, root);
+        }
+        assert_eq!(isqrt(1 << 62), 1 << 31);
+        assert_eq!(isqrt((1 << 62) - 1), (1 << 31) - 1);
+        assert_eq!(isqrt(u64::MAX), u32::MAX as u64);
+    }
+
+    #[test]
+    fn quadratic_voting_lets_small_donators_outvote_a_whale() {
+        // One whale terminating against a hundred small donators continuing, same donations in both modes
+        let mut donations = vec![100u64; 101];
+        donations[0] = 40_000;
+        let mut round = Round {
+            round_votes: Pubkey::default(),
+            round: 1,
+            target: 0,
+            balance: 0,
+            donators: 0,
+            status: RoundStatus::RoundTargetMet.to_u8(),
+            withdrawn: 0,
+            start_time: 0,
+            deadline: 0,
+            sqrt_donations: 0,
+        };
+        for &donation in &donations {
+            round.balance += donation;
+            round.update_sqrt_donations(0, donation);
+        }
+        assert_eq!(round.sqrt_donations, 200 + 100 * 10);
+
+        let tally = |quadratic_voting| {
+            let whale = donator_voting_power(donations[0], &round, quadratic_voting, 60);
+            let small_donators: u64 = donations[1..].iter()
+                .map(|&donation| donator_voting_power(donation, &round, quadratic_voting, 60))
+                .sum();
+            assert!(whale + small_donators <= 60 * BASIS_POINTS_PER_PERCENT);
+            (small_donators, whale)
+        };
+
+        let (linear_continue, linear_terminate) = tally(false);
+        assert_eq!((linear_continue, linear_terminate), (1_200, 4_800));
+        let (quadratic_continue, quadratic_terminate) = tally(true);
+        assert_eq!((quadratic_continue, quadratic_terminate), (5_000, 1_000));
+
+        // Topping up a donation replaces its old root in the total
+        round.update_sqrt_donations(100, 400);
+        assert_eq!(round.sqrt_donations, 200 + 99 * 10 + 20);
+    }
+
+    #[test]
+    fn ballots_are_only_accepted_within_the_voting_window() {
+        let round_votes = RoundVote {
+            continue_campaign: 0,
+            terminate_campaign: 0,
+            donators_voted: 0,
+            stakers_voted: 0,
+            start_time: 1_000,
+            end_time: 1_000 + DAY_IN_SECONDS as i64,
+            reveal_end_time: 1_000 + DAY_IN_SECONDS as i64,
+            voting_ended: false,
+            total_amount_staked: 0,
+            active_stakers: 0,
+        };
+
+        assert!(!round_votes.is_open(999));
+        assert!(round_votes.is_open(1_000));
+        assert!(round_votes.is_open(1_000 + DAY_IN_SECONDS as i64));
+        assert!(!round_votes.is_open(1_001 + DAY_IN_SECONDS as i64));
+        assert!(!round_votes.is_revealing(1_001 + DAY_IN_SECONDS as i64));
+    }
+
+    #[test]
+    fn committed_ballots_are_revealed_once_after_voting_closes() {
+        let round_votes = RoundVote {
+            continue_campaign: 0,
+            terminate_campaign: 0,
+            donators_voted: 0,
+            stakers_voted: 0,
+            start_time: 0,
+            end_time: 100,
+            reveal_end_time: 200,
+            voting_ended: false,
+            total_amount_staked: 0,
+            active_stakers: 0,
+        };
+        assert!(!round_votes.is_revealing(100));
+        assert!(round_votes.is_revealing(101));
+        assert!(round_votes.is_revealing(200));
+        assert!(!round_votes.is_revealing(201));
+
+        let salt = [7; 32];
+        let (voter_key, round_votes_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let mut voter = NextRoundVoter {
+            voting_power: 600,
+            has_voted: false,
+            choice: None,
+            commitment: Some(ballot_commitment(&voter_key, &round_votes_key, false, &salt)),
+            voter_type: VoterType::Staker.to_u8(),
+            bump: 0,
+        };
+
+        assert!(voter.reveal(&voter_key, &round_votes_key, true, &salt).is_err());
+        assert!(voter.reveal(&voter_key, &round_votes_key, false, &[8; 32]).is_err());
+        // A ballot copied from another voter or vote doesn't reveal
+        assert!(voter.reveal(&Pubkey::new_unique(), &round_votes_key, false, &salt).is_err());
+        assert!(voter.reveal(&voter_key, &Pubkey::new_unique(), false, &salt).is_err());
+        assert!(voter.reveal(&voter_key, &round_votes_key, false, &salt).is_ok());
+        assert!(voter.reveal(&voter_key, &round_votes_key, false, &salt).is_err());
+    }
+
+    #[test]
+    fn changing_a_ballot_moves_its_weight_between_tallies() {
+        let mut round_votes = RoundVote {
+            continue_campaign: 0,
+            terminate_campaign: 0,
+            donators_voted: 0,
+            stakers_voted: 0,
+            start_time: 0,
+            end_time: 0,
+            reveal_end_time: 0,
+            voting_ended: false,
+            total_amount_staked: 0,
+            active_stakers: 0,
+        };
+        let mut voter = NextRoundVoter {
+            voting_power: 600,
+            has_voted: false,
+            choice: None,
+            commitment: None,
+            voter_type: VoterType::Donator.to_u8(),
+            bump: 0,
+        };
+
+        round_votes.cast(&mut voter, true).unwrap();
+        assert_eq!((round_votes.continue_campaign, round_votes.terminate_campaign), (600, 0));
+        round_votes.cast(&mut voter, false).unwrap();
+        assert_eq!((round_votes.continue_campaign, round_votes.terminate_campaign), (0, 600));
+        round_votes.cast(&mut voter, false).unwrap();
+        assert_eq!((round_votes.continue_campaign, round_votes.terminate_campaign), (0, 600));
+        assert_eq!(round_votes.donators_voted, 1);
+        assert_eq!(voter.choice, Some(false));
+
+        // Migrated ballots don't know which tally they were added to
+        let mut migrated = NextRoundVoter { has_voted: true, choice: None, ..voter };
+        assert!(round_votes.cast(&mut migrated, true).is_err());
+        assert_eq!((round_votes.continue_campaign, round_votes.terminate_campaign), (0, 600));
+    }
+
+    // Flexible two round campaign whose first round, `round_key`, raised 300 from two donors
+    fn test_campaign(round_key: Pubkey) -> (Campaign, Round) {
+        let campaign = Campaign {
+            fundstarter: Pubkey::default(),
+            index: 0,
+            vault: Pubkey::default(),
+            description: String::new(),
+            target: 600,
+            cid: String::new(),
+            balance: 300,
+            token_mint: Pubkey::default(),
+            status: CampaignStatus::CampaignActive.to_u8(),
+            can_start_next_round: true,
+            total_rounds: 2,
+            active_round: 1,
+            active_round_address: round_key,
+            start_time: 0,
+            moderation_end_time: MODERATION_PERIOD_IN_SECONDS,
+            is_valid_votes: 0,
+            not_valid_votes: 0,
+            moderator_votes: 0,
+            is_valid_campaign: true,
+            funding_mode: FundingMode::Flexible.to_u8(),
+            commit_reveal: false,
+            quadratic_voting: false,
+            bump: 0,
+        };
+        let mut round = Round {
+            round_votes: Pubkey::default(),
+            round: 1,
+            target: 300,
+            balance: 0,
+            donators: 2,
+            status: RoundStatus::RoundTargetMet.to_u8(),
+            withdrawn: 0,
+            start_time: 0,
+            deadline: 0,
+            sqrt_donations: 0,
+        };
+        for donation in [100, 200] {
+            round.balance += donation;
+            round.update_sqrt_donations(0, donation);
+        }
+        (campaign, round)
+    }
+
+    #[test]
+    fn terminated_rounds_refund_their_donors_in_full_and_pay_out_nothing() {
+        let round_key = Pubkey::new_unique();
+        let (mut campaign, mut round) = test_campaign(round_key);
+        let mut round_votes = RoundVote {
+            continue_campaign: 1_000,
+            terminate_campaign: 3_000,
+            donators_voted: 2,
+            stakers_voted: 0,
+            start_time: 0,
+            end_time: 0,
+            reveal_end_time: 0,
+            voting_ended: false,
+            total_amount_staked: 1_000,
+            active_stakers: 2,
+        };
+
+        // Half of the eligible voters isn't a quorum, a majority to continue never terminates
+        assert!(!round_votes.terminates_campaign(round.donators, 50));
+        round_votes.stakers_voted = 1;
+        assert!(round_votes.terminates_campaign(round.donators, 50));
+        round_votes.continue_campaign = 3_000;
+        assert!(!round_votes.terminates_campaign(round.donators, 50));
+
+        // Until the vote ends the round is neither paid out nor refunded
+        assert!(!campaign.releases(&round));
+        assert!(campaign.refund_for(round_key, &round, 100, 300).is_err());
+
+        campaign.end_round_vote(&mut round, true).unwrap();
+        assert_eq!(campaign.status, CampaignStatus::CampaignRefunding.to_u8());
+        assert_eq!(round.status, RoundStatus::RoundTerminated.to_u8());
+        assert!(!campaign.can_start_next_round);
+        assert!(!campaign.releases(&round));
+
+        // Each donor of the terminated round gets their whole contribution back, other rounds nothing
+        assert!(campaign.refund_for(Pubkey::new_unique(), &round, 100, 300).is_err());
+        for contribution in [100, 200] {
+            assert_eq!(campaign.refund_for(round_key, &round, contribution, 300).unwrap(), contribution);
+            campaign.record_refund(&mut round, contribution);
+        }
+        assert_eq!(round.balance, 0);
+        assert_eq!(round.sqrt_donations, 0);
+        assert_eq!(campaign.balance, 0);
+
+        // All-or-nothing campaigns paid nothing out yet, so every round gets refunded
+        campaign.funding_mode = FundingMode::AllOrNothing.to_u8();
+        campaign.status = CampaignStatus::CampaignActive.to_u8();
+        campaign.end_round_vote(&mut round, true).unwrap();
+        assert_eq!(campaign.status, CampaignStatus::CampaignFailed.to_u8());
+
+        // Rounds voters approve are released to the fundstarter
+        campaign.status = CampaignStatus::CampaignActive.to_u8();
+        campaign.end_round_vote(&mut round, false).unwrap();
+        assert!(campaign.releases(&round));
+    }
+
+    #[test]
+    fn tallies_leave_campaigns_frozen_by_moderation_alone() {
+        let round_key = Pubkey::new_unique();
+        let (mut campaign, mut round) = test_campaign(round_key);
+
+        // Frozen while the round vote was still open, then the vote terminates it
+        campaign.status = CampaignStatus::CampaignFrozen.to_u8();
+        campaign.is_valid_campaign = false;
+        assert!(campaign.end_round_vote(&mut round, true).is_err());
+        assert_eq!(campaign.status, CampaignStatus::CampaignFrozen.to_u8());
+        assert_eq!(round.status, RoundStatus::RoundTargetMet.to_u8());
+        assert!(campaign.can_start_next_round);
+
+        // Donors of every round keep their pro-rata refund of what is left in the vault
+        assert_eq!(campaign.refund_for(Pubkey::new_unique(), &round, 100, 150).unwrap(), 50);
+        assert_eq!(campaign.refund_for(round_key, &round, 200, 150).unwrap(), 100);
+    }
+
+    #[test]
+    fn vaults_close_once_the_campaign_stopped_raising() {
+        let (mut campaign, _round) = test_campaign(Pubkey::new_unique());
+        assert!(!campaign.stopped_raising());
+
+        // Withdrawing the final round ends the campaign, earlier rounds may still be left to withdraw
+        campaign.status = CampaignStatus::CampaignEnded.to_u8();
+        assert!(campaign.stopped_raising());
+        campaign.status = CampaignStatus::CampaignPartiallyFunded.to_u8();
+        assert!(campaign.stopped_raising());
+        campaign.status = CampaignStatus::CampaignFrozen.to_u8();
+        assert!(!campaign.stopped_raising());
+    }
+
+    #[test]
+    fn stake_stays_locked_while_backing_votes() {
+        let mut stake_account = StakeAccount {
+            stake_time: 0,
+            last_deposit_time: 0,
+            deposit: 100,
+            reward: 0,
+            reward_debt: 0,
+            locked_until: 1_000,
+            active_moderations: 1,
+            moderations_close_at: 0,
+            pending_unstake: 0,
+            unstake_available_at: 0,
+            lock_end: 0,
+            delegated: false,
+            delegated_stake: 0,
+            last_revocation_time: 0,
+        };
+
+        // Moderations hold the stake until they are released, however long that takes
+        assert!(!stake_account.is_unlocked(1_001));
+        assert!(!stake_account.is_unlocked(i64::MAX));
+        stake_account.release_moderation();
+        assert!(!stake_account.is_unlocked(1_000));
+        assert!(stake_account.is_unlocked(1_001));
+
+        stake_account.add_moderation(0);
+        stake_account.add_moderation(0);
+        stake_account.release_moderation();
+        assert!(!stake_account.is_unlocked(2_000));
+        stake_account.release_moderation();
+        assert!(stake_account.is_unlocked(2_000));
+
+        stake_account.lock_end = 2_000;
+        assert!(!stake_account.is_unlocked(1_999));
+        assert!(stake_account.is_unlocked(2_000));
+    }
+
+    #[test]
+    fn locked_and_aged_stake_votes_with_a_decaying_boost() {
+        let year = MAX_STAKE_LOCK_IN_SECONDS as i64;
+        let mut stake_account = test_stake_account(1_000);
+        stake_account.lock_end = year;
+
+        // Fresh stake with a full lock, then halfway through it with half a year of age
+        assert_eq!(stake_account.boost(0), MAX_BASIS_POINTS + MAX_LOCK_BOOST_BASIS_POINTS);
+        assert_eq!(
+            stake_account.boost(year / 2),
+            MAX_BASIS_POINTS + MAX_LOCK_BOOST_BASIS_POINTS / 2 + MAX_AGE_BOOST_BASIS_POINTS
+        );
+        // Once the lock has run out only the capped age boost is left
+        assert_eq!(stake_account.boost(2 * year), MAX_BASIS_POINTS + MAX_AGE_BOOST_BASIS_POINTS);
+
+        let mut unlocked = test_stake_account(1_000);
+        assert_eq!(unlocked.boost(0), MAX_BASIS_POINTS);
+
+        // A quarter of the pool holds a quarter of the staker rights, boosts multiply that
+        assert_eq!(staker_voting_power(&unlocked, 0, 4_000, 40, 0), 1_000);
+        assert_eq!(staker_voting_power(&stake_account, 0, 4_000, 40, 0), 2_000);
+        stake_account.stake_time = -(STAKE_AGE_FOR_FULL_BOOST_IN_SECONDS as i64);
+        assert_eq!(staker_voting_power(&stake_account, 0, 4_000, 40, 0), 2_500);
+        assert_eq!(staker_voting_power(&stake_account, 0, 0, 40, 0), 0);
+
+        // Alone in the pool, unboosted stake already holds all of the staker rights and boosts can't add to them
+        assert_eq!(staker_voting_power(&unlocked, 0, 1_000, 40, 0), 4_000);
+        assert_eq!(staker_voting_power(&stake_account, 0, 1_000, 40, 0), 4_000);
+
+        // Unlocked stake can opt in later, but a lock is never shortened
+        unlocked.extend_lock(year);
+        unlocked.extend_lock(year / 2);
+        assert_eq!(staker_voting_power(&unlocked, 0, 4_000, 40, 0), 2_000);
+    }
+
+    #[test]
+    fn delegates_vote_with_delegated_stake_at_base_weight() {
+        let year = MAX_STAKE_LOCK_IN_SECONDS as i64;
+        let mut delegate = test_stake_account(600);
+        assert_eq!(staker_voting_power(&delegate, 400, 4_000, 40, 0), 1_000);
+
+        // Only the delegate's own deposit is boosted by its lock
+        delegate.lock_end = year;
+        assert_eq!(staker_voting_power(&delegate, 400, 4_000, 40, 0), 1_600);
+
+        // Each delegation only counts in votes opened after it, later ones don't hold back earlier ones
+        let delegation = |delegated_at| Delegation {
+            delegator: Pubkey::default(),
+            delegate: Pubkey::default(),
+            amount: 400,
+            delegated_at,
+            bump: 0,
+        };
+        assert!(!delegation(1_000).counts_at(999));
+        assert!(delegation(1_000).counts_at(1_000));
+        assert!(delegation(500).counts_at(999));
+    }
+
+    #[test]
+    fn revoked_stake_stays_locked_while_the_delegate_may_count_it() {
+        let mut delegate = test_stake_account(600);
+        delegate.locked_until = 2_000;
+        let mut delegator = test_stake_account(400);
+        delegator.inherit_locks(&delegate);
+        assert!(!delegator.is_unlocked(2_000));
+        assert!(delegator.is_unlocked(2_001));
+
+        // Moderations have no end time of their own, the campaign deadlines bound them
+        delegate.add_moderation(5_000);
+        delegate.add_moderation(4_000);
+        let mut delegator = test_stake_account(400);
+        delegator.inherit_locks(&delegate);
+        assert!(!delegator.is_unlocked(5_000));
+        assert!(delegator.is_unlocked(5_001));
+    }
+
+    #[test]
+    fn revoked_stake_only_backs_votes_opened_after_the_revocation() {
+        let mut delegator = test_stake_account(400);
+        assert!(delegator.undelegated_since(1));
+
+        delegator.last_revocation_time = 1_000;
+        assert!(!delegator.undelegated_since(1_000));
+        assert!(delegator.undelegated_since(1_001));
+    }
+
+    fn test_config() -> Config {
+        Config {
+            admin: Pubkey::default(),
+            pending_admin: Pubkey::default(),
+            native_token_mint: Pubkey::default(),
+            donation_fee: 0,
+            staking_initialized: true,
+            active_stakers: 0,
+            total_amount_staked: 0,
+            round_voting_period_in_days: 1,
+            minimum_required_vote_percentage: 30,
+            minimum_moderation_vote_percentage: 30,
+            donator_voting_rights: 60,
+            staker_voting_rights: 40,
+            staker_moderation_rights: 100,
+            unstake_cooldown_in_seconds: 0,
+            moderation_slash_percentage: 10,
+            staking_pool: Pubkey::default(),
+            reward_per_token: 0,
+            bump: 0,
+        }
+    }
+
+    fn test_stake_account(deposit: u64) -> StakeAccount {
+        StakeAccount {
+            stake_time: 0,
+            last_deposit_time: 0,
+            deposit,
+            reward: 0,
+            reward_debt: 0,
+            locked_until: 0,
+            active_moderations: 0,
+            moderations_close_at: 0,
+            pending_unstake: 0,
+            unstake_available_at: 0,
+            lock_end: 0,
+            delegated: false,
+            delegated_stake: 0,
+            last_revocation_time: 0,
+        }
+    }
+
+    fn opened_stake(config: &mut Config, amount: u64) -> StakeAccount {
+        let mut stake_account = test_stake_account(0);
+        config.open_stake(&mut stake_account, amount, 0, 0);
+        stake_account
+    }
+
+    // Completes a pending unstake, dropping the account when `complete_unstake` closes it.
+    // Returns the rewards paid out alongside the deposit
+    fn complete_unstake(slot: &mut Option<StakeAccount>, current_time: i64) -> Result<u64> {
+        let stake_account = slot.as_mut().unwrap();
+        let pending = stake_account.pending_unstake;
+        let (payout, closing) = stake_account.complete_unstake(current_time)?;
+        if closing {
+            *slot = None;
+        }
+        Ok(payout - pending)
+    }
+
+    // Unstake without waiting, the cooldown is 0 in `test_config`
+    fn unstake(config: &mut Config, slot: &mut Option<StakeAccount>, amount: u64, current_time: i64) -> Result<u64> {
+        config.request_unstake(slot.as_mut().unwrap(), amount, current_time)?;
+        complete_unstake(slot, current_time)
+    }
+
+    fn unclaimed_rewards(config: &Config, stake_account: &StakeAccount) -> u64 {
+        stake_account.clone().claim_rewards(config.reward_per_token).unwrap_or(0)
+    }
+
+    fn assert_config_matches<'a>(config: &Config, stakers: impl IntoIterator<Item = &'a StakeAccount>) {
+        let stakers: Vec<&StakeAccount> = stakers.into_iter().collect();
+        let deposits: Vec<u64> = stakers.iter()
+            .map(|s| s.deposit)
+            .filter(|deposit| *deposit > 0)
+            .collect();
+        assert_eq!(config.total_amount_staked, deposits.iter().sum::<u64>());
+        assert_eq!(config.active_stakers, deposits.len() as u64);
+        // Emptied accounts only stay open while an unstake is pending
+        assert!(stakers.iter().all(|s| s.deposit > 0 || s.pending_unstake > 0));
+    }
+
+    #[test]
+    fn losing_moderators_cannot_unstake_ahead_of_the_slash() {
+        let mut config = test_config();
+        let mut slot = Some(opened_stake(&mut config, 1_000));
+        slot.as_mut().unwrap().add_moderation(0);
+
+        assert!(config.request_unstake(slot.as_mut().unwrap(), 1_000, 1).is_err());
+        assert!(config.request_unstake(slot.as_mut().unwrap(), 600, 1).is_err());
+
+        let slash = config.release_moderation(slot.as_mut().unwrap(), true).unwrap();
+        assert_eq!(slash, 100);
+        assert_eq!(slot.as_ref().unwrap().deposit, 900);
+        assert_config_matches(&config, slot.as_ref());
+
+        assert_eq!(unstake(&mut config, &mut slot, 900, 1).unwrap(), 0);
+        assert!(slot.is_none());
+        assert_eq!(config.total_amount_staked, 0);
+    }
+
+    #[test]
+    fn added_stake_is_averaged_into_stake_age() {
+        let mut config = test_config();
+        let mut stake_account = opened_stake(&mut config, 100);
+        config.add_stake(&mut stake_account, 300, 1_000);
+
+        assert_eq!(stake_account.stake_time, 750);
+        assert_eq!(stake_account.last_deposit_time, 1_000);
+        assert_eq!(stake_account.deposit, 400);
+        assert_config_matches(&config, [&stake_account]);
+    }
+
+    #[test]
+    fn rewards_are_split_by_stake_at_funding_time() {
+        let mut config = test_config();
+        let mut first = opened_stake(&mut config, 100);
+        let second = opened_stake(&mut config, 300);
+
+        config.distribute_rewards(40);
+        // Joining after the rewards were funded doesn't earn any of them
+        let mut third = opened_stake(&mut config, 400);
+        assert_eq!(unclaimed_rewards(&config, &third), 0);
+
+        // Adding stake keeps what was earned so far
+        config.add_stake(&mut first, 200, 0);
+        config.distribute_rewards(100);
+
+        let mut second = Some(second);
+        let reward_per_token = config.reward_per_token;
+        assert_eq!(first.claim_rewards(reward_per_token).unwrap(), 10 + 30);
+        assert_eq!(second.as_mut().unwrap().claim_rewards(reward_per_token).unwrap(), 30 + 30);
+        assert_eq!(third.claim_rewards(reward_per_token).unwrap(), 40);
+        assert!(first.claim_rewards(reward_per_token).is_err());
+
+        // Unclaimed rewards are paid out when the whole deposit is withdrawn, and cleared
+        config.distribute_rewards(100);
+        assert_eq!(unstake(&mut config, &mut second, 100, 1).unwrap(), 0);
+        let mut emptied = second.clone().unwrap();
+        assert_eq!(unstake(&mut config, &mut second, 200, 1).unwrap(), 30);
+        assert!(second.is_none());
+
+        config.request_unstake(&mut emptied, 200, 1).unwrap();
+        assert_eq!(emptied.complete_unstake(1).unwrap(), (200 + 30, true));
+        assert_eq!(emptied.reward, 0);
+    }
+
+    #[test]
+    fn only_moderators_who_voted_against_the_outcome_are_slashed() {
+        let moderator = |has_voted, thumbs_up| Moderator {
+            voting_power: 0,
+            has_voted,
+            thumbs_up,
+            moderator_type: ModeratorType::Staker.to_u8(),
+        };
+
+        assert!(moderator(true, true).voted_against(true));
+        assert!(moderator(true, false).voted_against(false));
+        assert!(!moderator(true, false).voted_against(true));
+        assert!(!moderator(true, true).voted_against(false));
+        assert!(!moderator(false, false).voted_against(false));
+
+        let mut config = test_config();
+        assert_eq!(config.moderation_slash_for(1_000), 100);
+        assert_eq!(config.moderation_slash_for(9), 0);
+        config.moderation_slash_percentage = MAX_MODERATION_SLASH_PERCENTAGE;
+        // Never the whole deposit, so a slashed staker stays active
+        assert_eq!(config.moderation_slash_for(1), 0);
+        assert_eq!(config.moderation_slash_for(u64::MAX), u64::MAX / 2);
+    }
+
+    #[test]
+    fn partial_unstake_keeps_the_staker_active() {
+        let mut config = test_config();
+        let mut slot = Some(opened_stake(&mut config, 100));
+
+        unstake(&mut config, &mut slot, 40, 1).unwrap();
+        assert_eq!(config.active_stakers, 1);
+        assert_eq!(config.total_amount_staked, 60);
+        assert_eq!(slot.as_ref().unwrap().deposit, 60);
+
+        unstake(&mut config, &mut slot, 60, 1).unwrap();
+        assert_eq!(config.active_stakers, 0);
+        assert_eq!(config.total_amount_staked, 0);
+        assert!(slot.is_none());
+    }
+
+    #[test]
+    fn unstake_requests_remove_voting_stake_before_the_cooldown() {
+        let mut config = test_config();
+        config.unstake_cooldown_in_seconds = 1_000;
+        let mut stake_account = opened_stake(&mut config, 100);
+
+        config.request_unstake(&mut stake_account, 30, 1).unwrap();
+        config.request_unstake(&mut stake_account, 70, 1_000).unwrap();
+        assert_eq!(stake_account.deposit, 0);
+        assert_eq!(stake_account.pending_unstake, 100);
+        assert_config_matches(&config, [&stake_account]);
+
+        // The second request restarted the cooldown
+        assert!(stake_account.complete_unstake(1_999).is_err());
+        assert_eq!(stake_account.complete_unstake(2_000).unwrap(), (100, true));
+        assert!(stake_account.complete_unstake(2_000).is_err());
+    }
+
+    #[test]
+    fn restaking_during_the_cooldown_rejoins_the_stakers() {
+        let mut config = test_config();
+        let mut slot = Some(opened_stake(&mut config, 100));
+
+        config.request_unstake(slot.as_mut().unwrap(), 100, 1).unwrap();
+        assert_eq!(config.active_stakers, 0);
+        config.add_stake(slot.as_mut().unwrap(), 50, 0);
+        assert_eq!(config.active_stakers, 1);
+
+        // The account stays open since it still holds a deposit
+        assert_eq!(complete_unstake(&mut slot, 1).unwrap(), 0);
+        assert_eq!(slot.as_ref().unwrap().deposit, 50);
+        assert_config_matches(&config, slot.iter());
+    }
+
+    #[test]
+    fn invalid_unstake_leaves_totals_untouched() {
+        let mut config = test_config();
+        let mut slot = Some(opened_stake(&mut config, 100));
+
+        assert!(unstake(&mut config, &mut slot, 0, 1).is_err());
+        assert!(unstake(&mut config, &mut slot, 101, 1).is_err());
+
+        // Locked, delegated, or holding stake delegated to it and asking for all of it
+        let stake_account = slot.as_mut().unwrap();
+        stake_account.lock_end = 2;
+        assert!(config.request_unstake(stake_account, 50, 1).is_err());
+        stake_account.lock_end = 0;
+        stake_account.delegated = true;
+        assert!(config.request_unstake(stake_account, 50, 1).is_err());
+        stake_account.delegated = false;
+        stake_account.delegated_stake = 400;
+        assert!(config.request_unstake(stake_account, 100, 1).is_err());
+
+        assert_eq!(slot.as_ref().unwrap().deposit, 100);
+        assert_config_matches(&config, slot.iter());
+        assert!(unstake(&mut config, &mut slot, 50, 1).is_ok());
+    }
+
+    #[test]
+    fn config_tracks_stakers_across_stake_and_unstake_sequences() {
+        // Small LCG so every run replays the same sequences
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = |bound: u64| {
+            seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+            (seed >> 33) % bound
+        };
+
+        for _ in 0..50 {
+            let mut config = test_config();
+            let mut stakers: Vec<Option<StakeAccount>> = vec![None; 8];
+            let mut funded = 0;
+            let mut paid = 0;
+
+            for step in 1..=200 {
+                let slot = &mut stakers[next(8) as usize];
+                match slot {
+                    None => *slot = Some(opened_stake(&mut config, next(1_000) + 1)),
+                    // whole deposit is cooling down
+                    Some(stake_account) if stake_account.deposit == 0 => match next(2) {
+                        0 => paid += complete_unstake(slot, step).unwrap(),
+                        _ => config.add_stake(stake_account, next(1_000) + 1, step),
+                    },
+                    Some(stake_account) => {
+                        let deposit = stake_account.deposit;
+                        let pending = stake_account.pending_unstake;
+                        match next(9) {
+                            // withdraw everything
+                            0 => paid += unstake(&mut config, slot, deposit, step).unwrap(),
+                            // more than the deposit is rejected
+                            1 => assert!(unstake(&mut config, slot, deposit + 1, step).is_err()),
+                            2 => config.add_stake(stake_account, next(1_000) + 1, step),
+                            3 => {
+                                let amount = next(500) + 1;
+                                config.distribute_rewards(amount);
+                                funded += amount;
+                            }
+                            4 => paid += stake_account.claim_rewards(config.reward_per_token).unwrap_or(0),
+                            5 => config.request_unstake(stake_account, next(deposit) + 1, step).unwrap(),
+                            6 if pending > 0 => paid += complete_unstake(slot, step).unwrap(),
+                            6 => assert!(complete_unstake(slot, step).is_err()),
+                            _ => paid += unstake(&mut config, slot, next(deposit) + 1, step).unwrap(),
+                        }
+                    }
+                }
+                assert_config_matches(&config, stakers.iter().flatten());
+
+                // Rounding can only leave dust in the pool, never pay out more than was funded
+                let unclaimed: u64 = stakers.iter().flatten()
+                    .map(|stake_account| unclaimed_rewards(&config, stake_account))
+                    .sum();
+                assert!(paid + unclaimed <= funded);
+            }
+        }
+    }
+}
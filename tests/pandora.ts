@@ -7,6 +7,7 @@ import {
   airdrop,
   getConfigPDA,
   getCampaignPDA,
+  getCreatorProfilePDA,
   getVaultPDA,
   getRoundPDA,
   createAssociatedTokenAccount,
@@ -16,10 +17,13 @@ import {
   getStakeAccountPDA,
   getStakingPoolPDA,
   getVoterAccountPDA,
-  getModeratorAccountPDA
+  getModeratorAccountPDA,
+  getTreasuryPDA,
+  getDelegationPDA
 } from "./utils";
 import { assert, config, expect } from "chai";
 import { createAssociatedTokenAccountInstruction } from "@solana/spl-token";
+import { sha256 } from "js-sha256";
 
 describe("pandora", async () => {
   // Configure the client to use the local cluster.
@@ -33,9 +37,348 @@ describe("pandora", async () => {
   let [configPDA, configBump] = await getConfigPDA(program);
   let nativeMintAddress: anchor.web3.PublicKey;
   let nativeMintAuthority: anchor.web3.Keypair;
+  // Collects the donation fees of native token campaigns, created by the first of them
+  let nativeTreasuryPDA: anchor.web3.PublicKey;
+
+  type CampaignAccounts = {
+    fundstarter: anchor.web3.Keypair,
+    campaign: anchor.web3.PublicKey,
+    vault: anchor.web3.PublicKey,
+    round: anchor.web3.PublicKey,
+  };
+
+  // Starts a native token campaign for a fresh fundstarter
+  async function newCampaign(target: number, numberOfRounds: number, initialTarget: number,
+    deadline: number | null = null, fundingMode: object = { flexible: {} }, commitReveal: boolean = false,
+    quadraticVoting: boolean = false)
+  : Promise<CampaignAccounts> {
+    let fundstarter = anchor.web3.Keypair.generate();
+    await airdrop(provider.connection, fundstarter, 2);
+    let [creatorProfilePDA, _creatorProfileBump] = await getCreatorProfilePDA(program, fundstarter.publicKey);
+    let [campaignPDA, _campaignBump] = await getCampaignPDA(program, fundstarter.publicKey);
+    let [vaultPDA, _vaultBump] = await getVaultPDA(program, campaignPDA);
+    let [round1PDA, _roundBump] = await getRoundPDA(program, campaignPDA, 1);
+
+    await program.methods
+      .startCampaign("Test campaign", new anchor.BN(target), numberOfRounds, new anchor.BN(initialTarget), "QmTestCampaignCid",
+        deadline === null ? null : new anchor.BN(deadline), fundingMode, commitReveal, quadraticVoting)
+      .accounts({
+        fundstarter: fundstarter.publicKey,
+        creatorProfile: creatorProfilePDA,
+        campaign: campaignPDA,
+        vault: vaultPDA,
+        round: round1PDA,
+        config: configPDA,
+        treasury: nativeTreasuryPDA,
+        tokenMint: nativeMintAddress,
+      })
+      .signers([fundstarter])
+      .rpc();
+
+    return { fundstarter, campaign: campaignPDA, vault: vaultPDA, round: round1PDA };
+  }
+
+  // Creates a wallet holding `amount` native tokens
+  async function fundedWallet(amount: number): Promise<[anchor.web3.Keypair, anchor.web3.PublicKey]> {
+    let owner = anchor.web3.Keypair.generate();
+    await airdrop(provider.connection, owner, 1);
+    let wallet = await createAssociatedTokenAccount(program, owner, nativeMintAddress);
+    await mintTokensToWallet(wallet, amount, owner, nativeMintAddress, nativeMintAuthority, program);
+    return [owner, wallet];
+  }
+
+  // Voting power of a stake weighing `boost` basis points of its deposit, capped at the rights.
+  // Stakes here are seconds old, so unless they are locked their boost is the base 10_000
+  function stakerPower(deposit: number, totalStake: number, rights: number, boost: number = 10_000): number {
+    return Math.min(Math.trunc(deposit * boost * rights * 100 / (totalStake * 10_000)), rights * 100);
+  }
+
+  // Stakes `amount` native tokens from a fresh wallet
+  async function newStaker(amount: number, lockDuration: number = 0)
+  : Promise<[anchor.web3.Keypair, anchor.web3.PublicKey, anchor.web3.PublicKey]> {
+    let [stakingPoolPDA, _poolBump] = await getStakingPoolPDA(program, configPDA);
+    let [staker, stakerWallet] = await fundedWallet(amount);
+    let [stakeAccount, _stakeBump] = await getStakeAccountPDA(program, staker.publicKey);
+    await program.methods
+      .stake(new anchor.BN(amount), new anchor.BN(lockDuration))
+      .accounts({
+        config: configPDA,
+        stakeAccount: stakeAccount,
+        stakerTokenAccount: stakerWallet,
+        stakingPool: stakingPoolPDA,
+        staker: staker.publicKey,
+        mint: nativeMintAddress
+      })
+      .signers([staker])
+      .rpc();
+    return [staker, stakerWallet, stakeAccount];
+  }
+
+  async function requestUnstake(staker: anchor.web3.Keypair, stakeAccount: anchor.web3.PublicKey, amount: number) {
+    await program.methods
+      .requestUnstake(new anchor.BN(amount))
+      .accounts({
+        config: configPDA,
+        staker: staker.publicKey,
+        stakeAccount: stakeAccount,
+      })
+      .signers([staker])
+      .rpc();
+  }
+
+  async function completeUnstake(staker: anchor.web3.Keypair, stakerWallet: anchor.web3.PublicKey,
+    stakeAccount: anchor.web3.PublicKey) {
+    let [stakingPoolPDA, _poolBump] = await getStakingPoolPDA(program, configPDA);
+    await program.methods
+      .completeUnstake()
+      .accounts({
+        config: configPDA,
+        staker: staker.publicKey,
+        stakeAccount: stakeAccount,
+        stakingPool: stakingPoolPDA,
+        stakerTokenAccount: stakerWallet,
+      })
+      .signers([staker])
+      .rpc();
+  }
+
+  async function governanceParams() {
+    let configState = await program.account.config.fetch(configPDA);
+    return {
+      roundVotingPeriodInDays: configState.roundVotingPeriodInDays,
+      minimumRequiredVotePercentage: configState.minimumRequiredVotePercentage,
+      minimumModerationVotePercentage: configState.minimumModerationVotePercentage,
+      donatorVotingRights: configState.donatorVotingRights,
+      stakerVotingRights: configState.stakerVotingRights,
+      stakerModerationRights: configState.stakerModerationRights,
+      donationFee: configState.donationFee,
+      unstakeCooldownInSeconds: configState.unstakeCooldownInSeconds,
+      moderationSlashPercentage: configState.moderationSlashPercentage,
+    };
+  }
+
+  async function updateGovernanceParams(params) {
+    await program.methods
+      .updateConfig(params)
+      .accounts({ config: configPDA, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+  }
+
+  async function moderateCampaign(campaign: anchor.web3.PublicKey, staker: anchor.web3.Keypair,
+    stakeAccount: anchor.web3.PublicKey, thumbsUp: boolean) {
+    let [modAccountPDA, _modBump] = await getModeratorAccountPDA(program, campaign, staker.publicKey);
+    await program.methods
+      .initStakerModeration()
+      .accounts({
+        config: configPDA,
+        campaign: campaign,
+        moderatorAccount: modAccountPDA,
+        staker: staker.publicKey,
+        stakeAccount: stakeAccount,
+      })
+      .signers([staker])
+      .rpc();
+    await program.methods
+      .moderate(thumbsUp)
+      .accounts({
+        config: configPDA,
+        campaign: campaign,
+        moderatorAccount: modAccountPDA,
+        moderator: staker.publicKey
+      })
+      .signers([staker])
+      .rpc();
+  }
+
+  async function delegateStake(delegator: anchor.web3.Keypair, delegate: anchor.web3.PublicKey) {
+    let [delegationPDA, _delegationBump] = await getDelegationPDA(program, delegator.publicKey);
+    let [stakeAccount, _stakeBump] = await getStakeAccountPDA(program, delegator.publicKey);
+    let [delegateStakeAccount, _delegateBump] = await getStakeAccountPDA(program, delegate);
+    await program.methods
+      .delegateStake()
+      .accounts({
+        delegation: delegationPDA,
+        delegator: delegator.publicKey,
+        stakeAccount: stakeAccount,
+        delegate: delegate,
+        delegateStakeAccount: delegateStakeAccount,
+      })
+      .signers([delegator])
+      .rpc();
+  }
+
+  async function revokeDelegation(delegator: anchor.web3.Keypair, delegate: anchor.web3.PublicKey) {
+    let [delegationPDA, _delegationBump] = await getDelegationPDA(program, delegator.publicKey);
+    let [stakeAccount, _stakeBump] = await getStakeAccountPDA(program, delegator.publicKey);
+    let [delegateStakeAccount, _delegateBump] = await getStakeAccountPDA(program, delegate);
+    await program.methods
+      .revokeDelegation()
+      .accounts({
+        delegation: delegationPDA,
+        delegator: delegator.publicKey,
+        stakeAccount: stakeAccount,
+        delegate: delegate,
+        delegateStakeAccount: delegateStakeAccount,
+      })
+      .signers([delegator])
+      .rpc();
+  }
+
+  // Anyone can release a moderation once the campaign concluded
+  async function releaseModeration(campaign: anchor.web3.PublicKey, staker: anchor.web3.PublicKey) {
+    let [modAccountPDA, _modBump] = await getModeratorAccountPDA(program, campaign, staker);
+    let [stakeAccount, _stakeBump] = await getStakeAccountPDA(program, staker);
+    let [stakingPoolPDA, _poolBump] = await getStakingPoolPDA(program, configPDA);
+    let [treasuryPDA, _treasuryBump] = await getTreasuryPDA(program, configPDA, nativeMintAddress);
+    await program.methods
+      .releaseModeration()
+      .accounts({
+        config: configPDA,
+        campaign: campaign,
+        moderatorAccount: modAccountPDA,
+        staker: staker,
+        stakeAccount: stakeAccount,
+        stakingPool: stakingPoolPDA,
+        treasury: treasuryPDA,
+      })
+      .rpc();
+  }
+
+  async function claimRefund(accounts: CampaignAccounts, round: anchor.web3.PublicKey,
+    donator: anchor.web3.Keypair, donatorWallet: anchor.web3.PublicKey) {
+    let [donatorAccountPDA, _donatorBump] = await getDonatorAccountPDA(program, round, donator.publicKey);
+    await program.methods
+      .claimRefund()
+      .accounts({
+        campaign: accounts.campaign,
+        round: round,
+        vault: accounts.vault,
+        donatorAccount: donatorAccountPDA,
+        donator: donator.publicKey,
+        donatorTokenAccount: donatorWallet,
+      })
+      .signers([donator])
+      .rpc();
+  }
+
+  // Opens voting on the active round, which must have met its target
+  async function openVoting(accounts: CampaignAccounts): Promise<anchor.web3.PublicKey> {
+    let [roundVotesPDA, _roundVotesBump] = await getRoundVotesPDA(program, accounts.round);
+    await program.methods
+      .initializeVoting()
+      .accounts({
+        config: configPDA,
+        campaign: accounts.campaign,
+        roundVotes: roundVotesPDA,
+        fundstarter: accounts.fundstarter.publicKey,
+        round: accounts.round,
+        vault: accounts.vault,
+      })
+      .signers([accounts.fundstarter])
+      .rpc();
+    return roundVotesPDA;
+  }
+
+  // Delegations made to the staker before the vote opened are counted when passed along
+  async function initStakerVoting(accounts: CampaignAccounts, roundVotes: anchor.web3.PublicKey,
+    staker: anchor.web3.Keypair, stakeAccount: anchor.web3.PublicKey,
+    delegations: anchor.web3.PublicKey[] = []): Promise<anchor.web3.PublicKey> {
+    let [voterAccountPDA, _voterBump] = await getVoterAccountPDA(program, accounts.round, staker.publicKey);
+    await program.methods
+      .initStakerVoting()
+      .accounts({
+        config: configPDA,
+        campaign: accounts.campaign,
+        round: accounts.round,
+        roundVotes: roundVotes,
+        staker: staker.publicKey,
+        stakeAccount: stakeAccount,
+        voterAccount: voterAccountPDA
+      })
+      .remainingAccounts(delegations.map((pubkey) => ({ pubkey, isWritable: false, isSigner: false })))
+      .signers([staker])
+      .rpc();
+    return voterAccountPDA;
+  }
+
+  async function castVote(accounts: CampaignAccounts, roundVotes: anchor.web3.PublicKey,
+    voter: anchor.web3.Keypair, continueCampaign: boolean) {
+    let [voterAccountPDA, _voterBump] = await getVoterAccountPDA(program, accounts.round, voter.publicKey);
+    await program.methods
+      .vote(continueCampaign)
+      .accounts({
+        campaign: accounts.campaign,
+        round: accounts.round,
+        voterAccount: voterAccountPDA,
+        voter: voter.publicKey,
+        roundVotes: roundVotes
+      })
+      .signers([voter])
+      .rpc();
+  }
+
+  // Hash a commit-reveal ballot is committed as, matching the program's ballot_commitment
+  function ballotCommitment(voter: anchor.web3.PublicKey, roundVotes: anchor.web3.PublicKey,
+    continueCampaign: boolean, salt: Buffer): number[] {
+    return sha256.array(Buffer.concat([voter.toBuffer(), roundVotes.toBuffer(), Buffer.from([continueCampaign ? 1 : 0]), salt]));
+  }
+
+  async function commitVote(accounts: CampaignAccounts, roundVotes: anchor.web3.PublicKey,
+    voter: anchor.web3.Keypair, commitment: number[]) {
+    let [voterAccountPDA, _voterBump] = await getVoterAccountPDA(program, accounts.round, voter.publicKey);
+    await program.methods
+      .commitVote(commitment)
+      .accounts({
+        campaign: accounts.campaign,
+        round: accounts.round,
+        voterAccount: voterAccountPDA,
+        voter: voter.publicKey,
+        roundVotes: roundVotes
+      })
+      .signers([voter])
+      .rpc();
+  }
+
+  async function withdrawFrom(accounts: CampaignAccounts, round: anchor.web3.PublicKey,
+    destination: anchor.web3.PublicKey) {
+    await program.methods
+      .withdraw()
+      .accounts({
+        campaign: accounts.campaign,
+        round: round,
+        vault: accounts.vault,
+        fundstarter: accounts.fundstarter.publicKey,
+        walletToWithdrawTo: destination,
+      })
+      .signers([accounts.fundstarter])
+      .rpc();
+  }
+
+  async function donateTo(accounts: CampaignAccounts, donator: anchor.web3.Keypair,
+    donatorWallet: anchor.web3.PublicKey, amount: number): Promise<anchor.web3.PublicKey> {
+    let [treasuryPDA, _treasuryBump] = await getTreasuryPDA(program, configPDA, nativeMintAddress);
+    let [donatorAccountPDA, _donatorBump] = await getDonatorAccountPDA(program, accounts.round, donator.publicKey);
+    await program.methods
+      .donate(new anchor.BN(amount))
+      .accounts({
+        config: configPDA,
+        campaign: accounts.campaign,
+        vault: accounts.vault,
+        treasury: treasuryPDA,
+        round: accounts.round,
+        donatorAccount: donatorAccountPDA,
+        donator: donator.publicKey,
+        donatorTokenAccount: donatorWallet
+      })
+      .signers([donator])
+      .rpc();
+    return donatorAccountPDA;
+  }
 
   it("Initializes application state!", async () => {
    [nativeMintAddress, nativeMintAuthority] = await createTokenMint(provider.connection, admin);
+   [nativeTreasuryPDA] = await getTreasuryPDA(program, configPDA, nativeMintAddress);
   
    // Airdrop 2 sol to admin
    await airdrop(provider.connection, admin, 4);
@@ -55,6 +398,7 @@ describe("pandora", async () => {
    let configState = await program.account.config.fetch(configPDA);
 
    assert.ok(configState.admin.equals(admin.publicKey));
+   assert.ok(configState.pendingAdmin.equals(anchor.web3.PublicKey.default));
    assert.ok(configState.nativeTokenMint.equals(nativeMintAddress));
    assert.equal(configState.donationFee.toNumber(), 0);
    assert.equal(configState.stakingInitialized, false);
@@ -62,13 +406,178 @@ describe("pandora", async () => {
    assert.equal(configState.totalAmountStaked.toNumber(), 0);
    assert.equal(configState.roundVotingPeriodInDays, 1);
    assert.equal(configState.minimumRequiredVotePercentage, 30);
+   assert.equal(configState.minimumModerationVotePercentage, 30);
    assert.equal(configState.donatorVotingRights, 60);
    assert.equal(configState.stakerVotingRights, 40);
    assert.equal(configState.stakerModerationRights, 100);
+   assert.equal(configState.unstakeCooldownInSeconds.toNumber(), 7 * 24 * 60 * 60);
+   assert.equal(configState.moderationSlashPercentage, 0);
    assert.ok(configState.stakingPool.equals(anchor.web3.PublicKey.default));
    assert.equal(configState.bump, configBump);
   });
 
+  it("Updates governance config", async () => {
+    const defaultParams = {
+      roundVotingPeriodInDays: 1,
+      minimumRequiredVotePercentage: 30,
+      minimumModerationVotePercentage: 30,
+      donatorVotingRights: 60,
+      stakerVotingRights: 40,
+      stakerModerationRights: 100,
+      donationFee: new anchor.BN(0),
+      unstakeCooldownInSeconds: new anchor.BN(7 * 24 * 60 * 60),
+      moderationSlashPercentage: 0,
+    };
+
+    async function updateConfig(params, signer: anchor.web3.Keypair) {
+      await program.methods
+        .updateConfig(params)
+        .accounts({
+          config: configPDA,
+          admin: signer.publicKey,
+        })
+        .signers([signer])
+        .rpc();
+    }
+
+    async function expectRejected(params, signer: anchor.web3.Keypair, code: string) {
+      try {
+        await updateConfig(params, signer);
+        chai.assert(false, `Should fail with ${code}`);
+      } catch (_err) {
+        expect(_err).to.be.instanceOf(AnchorError);
+        const err: AnchorError = _err;
+        expect(err.error.errorCode.code).to.equal(code);
+        expect(err.program.equals(program.programId)).is.true;
+      }
+    }
+
+    await updateConfig({
+      ...defaultParams,
+      roundVotingPeriodInDays: 3,
+      minimumRequiredVotePercentage: 50,
+      minimumModerationVotePercentage: 40,
+      donatorVotingRights: 70,
+      stakerVotingRights: 30,
+      stakerModerationRights: 80,
+      donationFee: new anchor.BN(250),
+      unstakeCooldownInSeconds: new anchor.BN(60),
+      moderationSlashPercentage: 20,
+    }, admin);
+
+    let configState = await program.account.config.fetch(configPDA);
+    assert.equal(configState.roundVotingPeriodInDays, 3);
+    assert.equal(configState.minimumRequiredVotePercentage, 50);
+    assert.equal(configState.minimumModerationVotePercentage, 40);
+    assert.equal(configState.donatorVotingRights, 70);
+    assert.equal(configState.stakerVotingRights, 30);
+    assert.equal(configState.stakerModerationRights, 80);
+    assert.equal(configState.donationFee.toNumber(), 250);
+    assert.equal(configState.unstakeCooldownInSeconds.toNumber(), 60);
+    assert.equal(configState.moderationSlashPercentage, 20);
+
+    // Only the admin may update the config
+    let impostor = anchor.web3.Keypair.generate();
+    await airdrop(provider.connection, impostor, 1);
+    await expectRejected(defaultParams, impostor, "Unauthorized");
+
+    await expectRejected({ ...defaultParams, roundVotingPeriodInDays: 0 }, admin, "InvalidVotingPeriod");
+    await expectRejected({ ...defaultParams, minimumRequiredVotePercentage: 101 }, admin, "InvalidVotePercentage");
+    await expectRejected({ ...defaultParams, minimumModerationVotePercentage: 101 }, admin, "InvalidVotePercentage");
+    await expectRejected({ ...defaultParams, donatorVotingRights: 60, stakerVotingRights: 60 }, admin, "InvalidVotingRights");
+    await expectRejected({ ...defaultParams, donatorVotingRights: 50, stakerVotingRights: 40 }, admin, "InvalidVotingRights");
+    await expectRejected({ ...defaultParams, stakerModerationRights: 0 }, admin, "InvalidModerationRights");
+    await expectRejected({ ...defaultParams, stakerModerationRights: 101 }, admin, "InvalidModerationRights");
+    await expectRejected({ ...defaultParams, donationFee: new anchor.BN(10000) }, admin, "InvalidDonationFee");
+    await expectRejected({ ...defaultParams, unstakeCooldownInSeconds: new anchor.BN(31 * 24 * 60 * 60) }, admin, "InvalidUnstakeCooldown");
+    await expectRejected({ ...defaultParams, moderationSlashPercentage: 51 }, admin, "InvalidSlashPercentage");
+
+    // Rejected updates leave the config untouched
+    configState = await program.account.config.fetch(configPDA);
+    assert.equal(configState.roundVotingPeriodInDays, 3);
+    assert.equal(configState.donationFee.toNumber(), 250);
+
+    // Restore defaults for the rest of the suite
+    await updateConfig(defaultParams, admin);
+    configState = await program.account.config.fetch(configPDA);
+    assert.equal(configState.roundVotingPeriodInDays, 1);
+    assert.equal(configState.minimumRequiredVotePercentage, 30);
+    assert.equal(configState.minimumModerationVotePercentage, 30);
+    assert.equal(configState.donatorVotingRights, 60);
+    assert.equal(configState.stakerVotingRights, 40);
+    assert.equal(configState.stakerModerationRights, 100);
+    assert.equal(configState.donationFee.toNumber(), 0);
+    assert.equal(configState.unstakeCooldownInSeconds.toNumber(), 7 * 24 * 60 * 60);
+    assert.equal(configState.moderationSlashPercentage, 0);
+  });
+
+  it("Transfers admin in two steps", async () => {
+    let newAdmin = anchor.web3.Keypair.generate();
+    await airdrop(provider.connection, newAdmin, 1);
+
+    async function proposeAdmin(signer: anchor.web3.Keypair, proposed: anchor.web3.PublicKey) {
+      await program.methods
+        .proposeAdmin(proposed)
+        .accounts({ config: configPDA, admin: signer.publicKey })
+        .signers([signer])
+        .rpc();
+    }
+
+    async function acceptAdmin(signer: anchor.web3.Keypair) {
+      await program.methods
+        .acceptAdmin()
+        .accounts({ config: configPDA, pendingAdmin: signer.publicKey })
+        .signers([signer])
+        .rpc();
+    }
+
+    // Nothing to accept yet
+    try {
+      await acceptAdmin(newAdmin);
+      chai.assert(false, "Should fail because no transfer was proposed");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("NotPendingAdmin");
+    }
+
+    // Only the current admin can propose
+    try {
+      await proposeAdmin(newAdmin, newAdmin.publicKey);
+      chai.assert(false, "Should fail because signer isn't the admin");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("Unauthorized");
+    }
+
+    await proposeAdmin(admin, newAdmin.publicKey);
+    let configState = await program.account.config.fetch(configPDA);
+    assert.ok(configState.admin.equals(admin.publicKey));
+    assert.ok(configState.pendingAdmin.equals(newAdmin.publicKey));
+
+    await acceptAdmin(newAdmin);
+    configState = await program.account.config.fetch(configPDA);
+    assert.ok(configState.admin.equals(newAdmin.publicKey));
+    assert.ok(configState.pendingAdmin.equals(anchor.web3.PublicKey.default));
+
+    // The old admin has lost its rights
+    try {
+      await proposeAdmin(admin, admin.publicKey);
+      chai.assert(false, "Should fail because signer isn't the admin anymore");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("Unauthorized");
+    }
+
+    // Hand admin rights back for the rest of the suite
+    await proposeAdmin(newAdmin, admin.publicKey);
+    await acceptAdmin(admin);
+    configState = await program.account.config.fetch(configPDA);
+    assert.ok(configState.admin.equals(admin.publicKey));
+  });
+
   it("Simulates a campaign", async () => {
     // Initialize and start staking
     let [stakingPoolPDA, stakingPoolBump] = await getStakingPoolPDA(program, configPDA);
@@ -91,6 +600,9 @@ describe("pandora", async () => {
     configState = await program.account.config.fetch(configPDA);
     assert.equal(configState.stakingInitialized, true);
     assert.ok(configState.stakingPool.equals(stakingPoolPDA));
+
+    // Donation fees for campaigns in the native token go here
+    let [treasuryPDA, treasuryBump] = await getTreasuryPDA(program, configPDA, nativeMintAddress);
     
 
     async function stake (amount) : Promise<[anchor.web3.Keypair, anchor.web3.PublicKey]> {
@@ -102,7 +614,7 @@ describe("pandora", async () => {
       let [stakeAccount, _] = await getStakeAccountPDA(program, staker.publicKey);
       
       await program.methods
-        .stake(new anchor.BN(amount))
+        .stake(new anchor.BN(amount), new anchor.BN(0))
         .accounts({
           config: configPDA,
           stakeAccount: stakeAccount,
@@ -135,6 +647,7 @@ describe("pandora", async () => {
     let user = anchor.web3.Keypair.generate();
     await airdrop(provider.connection, user, 2);
 
+    let [creatorProfilePDA, creatorProfileBump] = await getCreatorProfilePDA(program, user.publicKey);
     let [campaignPDA, campaignBump] = await getCampaignPDA(program, user.publicKey);
     let [vaultPDA, vaultBump] = await getVaultPDA(program, campaignPDA);
     let [round1PDA, roundBump] = await getRoundPDA(program, campaignPDA, 1);
@@ -152,13 +665,20 @@ describe("pandora", async () => {
         new anchor.BN(expected_target),
         expected_number_of_rounds,
         new anchor.BN(expected_initial_target),
-        expected_cid
+        expected_cid,
+        null,
+        { flexible: {} },
+        false,
+        false
       )
       .accounts({
         fundstarter: user.publicKey,
+        creatorProfile: creatorProfilePDA,
         campaign: campaignPDA,
         vault: vaultPDA,
         round: round1PDA,
+        config: configPDA,
+        treasury: nativeTreasuryPDA,
         tokenMint: nativeMintAddress,
       })
       .signers([user])
@@ -166,6 +686,7 @@ describe("pandora", async () => {
     
     let campaignState = await program.account.campaign.fetch(campaignPDA);
     assert.ok(campaignState.fundstarter.equals(user.publicKey));
+    assert.equal(campaignState.index.toNumber(), 0);
     assert.ok(campaignState.vault.equals(vaultPDA));
     assert.equal(campaignState.description.toString(), expected_description);
     assert.equal(campaignState.target.toNumber(), expected_target);
@@ -177,10 +698,17 @@ describe("pandora", async () => {
     assert.equal(campaignState.totalRounds, expected_number_of_rounds);
     assert.equal(campaignState.activeRound, 1);
     assert.ok(campaignState.activeRoundAddress.equals(round1PDA));
-    assert.equal(campaignState.isValidVotes, 0);
-    assert.equal(campaignState.notValidVotes, 0);
+    assert.equal(campaignState.isValidVotes.toNumber(), 0);
+    assert.equal(campaignState.notValidVotes.toNumber(), 0);
     assert.equal(campaignState.moderatorVotes.toNumber(), 0);
     assert.equal(campaignState.isValidCampaign, true);
+    assert.equal(campaignState.fundingMode, 2);
+    assert.equal(campaignState.commitReveal, false);
+    assert.equal(campaignState.quadraticVoting, false);
+
+    // Starting a campaign creates its mint's treasury if needed, no admin involved
+    let treasuryState = await provider.connection.getTokenAccountBalance(treasuryPDA);
+    assert.equal(treasuryState.value.uiAmount, 0);
 
     let round1State = await program.account.round.fetch(round1PDA);
     assert.ok(round1State.roundVotes.equals(anchor.web3.PublicKey.default));
@@ -189,6 +717,8 @@ describe("pandora", async () => {
     assert.equal(round1State.balance.toNumber(), 0);
     assert.equal(round1State.donators.toNumber(), 0);
     assert.equal(round1State.status, 1);
+    assert.equal(round1State.withdrawn.toNumber(), 0);
+    assert.equal(round1State.deadline.toNumber(), 0);
 
     async function donate(amount, donator: anchor.web3.Keypair, program, campaign, round, vault)
     : Promise<anchor.web3.PublicKey> {
@@ -209,8 +739,10 @@ describe("pandora", async () => {
       await program.methods
         .donate(new anchor.BN(amount))
         .accounts({
+          config: configPDA,
           campaign: campaign,
           vault: vault,
+          treasury: treasuryPDA,
           round: round,
           donatorAccount: donatorAccountPDA,
           donator: donator.publicKey,
@@ -234,6 +766,7 @@ describe("pandora", async () => {
 
       let donatorAccountState1 = await program.account.donator.fetch(donatorAccountPDA);
       assert.equal(donatorAccountState1.amount.toNumber(), amount);
+      assert.equal(donatorAccountState1.donationCount.toNumber(), 1);
       assert.equal(donatorAccountState1.round, updatedCampaignState.activeRound);
       assert.equal(donatorAccountState1.round, updatedRoundState.round);
 
@@ -264,6 +797,7 @@ describe("pandora", async () => {
       await program.methods
       .initializeVoting()
       .accounts({
+        config: configPDA,
         campaign: campaignPDA,
         roundVotes: round1VotesAccount,
         fundstarter: user.publicKey,
@@ -325,6 +859,7 @@ describe("pandora", async () => {
     await program.methods
       .initializeVoting()
       .accounts({
+        config: configPDA,
         campaign: campaignPDA,
         roundVotes: round1VotesAccount,
         fundstarter: user.publicKey,
@@ -334,6 +869,13 @@ describe("pandora", async () => {
       .signers([user])
       .rpc();
 
+    let round1VotesState = await program.account.roundVote.fetch(round1VotesAccount);
+    let votingPeriod = (await program.account.config.fetch(configPDA)).roundVotingPeriodInDays * 24 * 60 * 60;
+    assert.equal(
+      round1VotesState.endTime.toNumber(),
+      round1VotesState.startTime.toNumber() + votingPeriod
+    );
+
 
     // Stakers ballot for the ability to cast votes
     async function stakerBallot(user: anchor.web3.Keypair, userStakeAccount: anchor.web3.PublicKey,
@@ -350,6 +892,7 @@ describe("pandora", async () => {
           config: configPDA,
           campaign: campaign,
           round: round,
+          roundVotes: round1VotesAccount,
           staker: staker.publicKey,
           stakeAccount: stakeAccount,
           voterAccount: voterAccountPDA
@@ -358,15 +901,16 @@ describe("pandora", async () => {
         .rpc();
     
       let configState = await program.account.config.fetch(configPDA);
+      let roundVotesState = await program.account.roundVote.fetch(round1VotesAccount);
       let voterAccountState = await program.account.nextRoundVoter.fetch(voterAccountPDA);
       let stakerAccountState = await program.account.stakeAccount.fetch(stakeAccount);
       
       let deposit = stakerAccountState.deposit.toNumber();
-      let totalStake = configState.totalAmountStaked.toNumber();
+      let totalStake = roundVotesState.totalAmountStaked.toNumber();
       let stakerRights = configState.stakerVotingRights;
-      let expected_voting_power = Math.trunc((deposit / totalStake) * stakerRights);
+      let expected_voting_power = stakerPower(deposit, totalStake, stakerRights);
 
-      assert.equal(voterAccountState.votingPower, expected_voting_power);
+      assert.equal(voterAccountState.votingPower.toNumber(), expected_voting_power);
       assert.equal(voterAccountState.hasVoted, false);
       assert.equal(voterAccountState.voterType, 2);
 
@@ -402,10 +946,10 @@ describe("pandora", async () => {
       let donation = await (await program.account.donator.fetch(donatorAccountPDA)).amount.toNumber();
       let votingRights = await (await program.account.config.fetch(config)).donatorVotingRights;
 
-      let expected_voting_power = Math.trunc((donation / totalDonations) * votingRights);
+      let expected_voting_power = Math.trunc(donation * votingRights * 100 / totalDonations);
 
       let voterAccountState = await program.account.nextRoundVoter.fetch(voterAccountPDA);
-      assert.equal(voterAccountState.votingPower, expected_voting_power);
+      assert.equal(voterAccountState.votingPower.toNumber(), expected_voting_power);
       assert.equal(voterAccountState.hasVoted, false);
       assert.equal(voterAccountState.voterType, 1);
 
@@ -423,8 +967,8 @@ describe("pandora", async () => {
       let voterAccountPDA = userVoterAccount;
 
       let roundVoteState = await program.account.roundVote.fetch(roundVoteAccount);
-      let continueVotes = roundVoteState.continueCampaign;
-      let terminateVotes = roundVoteState.terminateCampaign;
+      let continueVotes = roundVoteState.continueCampaign.toNumber();
+      let terminateVotes = roundVoteState.terminateCampaign.toNumber();
       let donatorsVoted = roundVoteState.donatorsVoted.toNumber();
       let stakersVoted = roundVoteState.stakersVoted.toNumber();
 
@@ -442,13 +986,13 @@ describe("pandora", async () => {
         .rpc();
 
       roundVoteState = await program.account.roundVote.fetch(roundVoteAccount);
-      let updatedContinueVotes = roundVoteState.continueCampaign;
-      let updatedTerminateVotes = roundVoteState.terminateCampaign;
+      let updatedContinueVotes = roundVoteState.continueCampaign.toNumber();
+      let updatedTerminateVotes = roundVoteState.terminateCampaign.toNumber();
       let updatedDonatorsVoted = roundVoteState.donatorsVoted.toNumber();
       let updatedStakersVoted = roundVoteState.stakersVoted.toNumber();
 
       let voterAccountState = await program.account.nextRoundVoter.fetch(voterAccountPDA);
-      let votingPower = voterAccountState.votingPower;
+      let votingPower = voterAccountState.votingPower.toNumber();
       assert.equal(voterAccountState.hasVoted, true);
 
       if(choice == true) {
@@ -478,8 +1022,8 @@ describe("pandora", async () => {
     await vote(donator2, donator2VoteAccount, round1PDA,  round1VotesAccount, true);
 
     let voteAccountState = await program.account.roundVote.fetch(round1VotesAccount);
-    console.log("Continue campaign votes?: ", voteAccountState.continueCampaign);
-    console.log("Terminate campaign votes?: ", voteAccountState.terminateCampaign);
+    console.log("Continue campaign votes?: ", voteAccountState.continueCampaign.toNumber());
+    console.log("Terminate campaign votes?: ", voteAccountState.terminateCampaign.toNumber());
     console.log(`${voteAccountState.donatorsVoted} donators voted this round`);
     console.log(`${voteAccountState.stakersVoted} stakers voted this round`);
 
@@ -501,7 +1045,7 @@ describe("pandora", async () => {
       .instruction();
 
     let tx2 = await program.methods
-      .startNextRound(new anchor.BN(450))
+      .startNextRound(new anchor.BN(450), null)
       .accounts({
         fundstarter: user.publicKey,
         campaign: campaignPDA,
@@ -539,6 +1083,7 @@ describe("pandora", async () => {
     user = anchor.web3.Keypair.generate();
     await airdrop(provider.connection, user, 2);
 
+    [creatorProfilePDA, creatorProfileBump] = await getCreatorProfilePDA(program, user.publicKey);
     [campaignPDA, campaignBump] = await getCampaignPDA(program, user.publicKey);
     [vaultPDA, vaultBump] = await getVaultPDA(program, campaignPDA);
     [round1PDA, roundBump] = await getRoundPDA(program, campaignPDA, 1);
@@ -556,13 +1101,20 @@ describe("pandora", async () => {
         new anchor.BN(expected_target),
         expected_number_of_rounds,
         new anchor.BN(expected_initial_target),
-        expected_cid
+        expected_cid,
+        null,
+        { flexible: {} },
+        false,
+        false
       )
       .accounts({
         fundstarter: user.publicKey,
+        creatorProfile: creatorProfilePDA,
         campaign: campaignPDA,
         vault: vaultPDA,
         round: round1PDA,
+        config: configPDA,
+        treasury: nativeTreasuryPDA,
         tokenMint: nativeMintAddress,
       })
       .signers([user])
@@ -598,9 +1150,9 @@ describe("pandora", async () => {
       let deposit = stakerAccountState.deposit.toNumber();
       let totalStake = configState.totalAmountStaked.toNumber();
       let stakerRights = configState.stakerModerationRights;
-      let expected_voting_power = Math.trunc((deposit / totalStake) * stakerRights);
+      let expected_voting_power = stakerPower(deposit, totalStake, stakerRights);
 
-      assert.equal(moderatorState.votingPower, expected_voting_power);
+      assert.equal(moderatorState.votingPower.toNumber(), expected_voting_power);
       assert.equal(moderatorState.hasVoted, false);
       assert.equal(moderatorState.moderatorType, 1);
 
@@ -613,8 +1165,8 @@ describe("pandora", async () => {
       let modAccountPDA = userModAccount;
 
       let campaignState = await program.account.campaign.fetch(campaign);
-      let positiveVotes = campaignState.isValidVotes;
-      let negativeVotes = campaignState.notValidVotes;
+      let positiveVotes = campaignState.isValidVotes.toNumber();
+      let negativeVotes = campaignState.notValidVotes.toNumber();
       let modsVoted = campaignState.moderatorVotes.toNumber();
 
       await program.methods 
@@ -629,12 +1181,12 @@ describe("pandora", async () => {
         .rpc();
 
       campaignState = await program.account.campaign.fetch(campaign);
-      let updatedPositiveVotes = campaignState.isValidVotes;
-      let updatedNegativeVotes = campaignState.notValidVotes;
+      let updatedPositiveVotes = campaignState.isValidVotes.toNumber();
+      let updatedNegativeVotes = campaignState.notValidVotes.toNumber();
       let updatedModsVoted = campaignState.moderatorVotes.toNumber();
 
       let modAccountState = await program.account.moderator.fetch(modAccountPDA);
-      let votingPower = modAccountState.votingPower;
+      let votingPower = modAccountState.votingPower.toNumber();
       assert.equal(modAccountState.hasVoted, true);
 
       if(choice == true) {
@@ -747,8 +1299,8 @@ describe("pandora", async () => {
         .rpc();
 
       campaignState = await program.account.campaign.fetch(campaignPDA);
-      console.log("Is_Valid_Campaign votes?: ", campaignState.isValidVotes);
-      console.log("Not_Valid_Campaign votes?: ", campaignState.notValidVotes);
+      console.log("Is_Valid_Campaign votes?: ", campaignState.isValidVotes.toNumber());
+      console.log("Not_Valid_Campaign votes?: ", campaignState.notValidVotes.toNumber());
       console.log("Is_Valid_Campaign?: ", campaignState.isValidCampaign);
       assert.equal(campaignState.isValidCampaign, true);
       console.log(`${campaignState.moderatorVotes} mods voted this round`);
@@ -770,8 +1322,8 @@ describe("pandora", async () => {
       await moderate(staker3, staker3ModAccount, campaignPDA, configPDA, false);
 
       campaignState = await program.account.campaign.fetch(campaignPDA);
-      console.log("Is_Valid_Campaign votes?: ", campaignState.isValidVotes);
-      console.log("Not_Valid_Campaign votes?: ", campaignState.notValidVotes);
+      console.log("Is_Valid_Campaign votes?: ", campaignState.isValidVotes.toNumber());
+      console.log("Not_Valid_Campaign votes?: ", campaignState.notValidVotes.toNumber());
       console.log("Is_Valid_Campaign?: ", campaignState.isValidCampaign);
       assert.equal(campaignState.isValidCampaign, false);
       console.log(`${campaignState.moderatorVotes} mods voted this round`);
@@ -798,7 +1350,1260 @@ describe("pandora", async () => {
       }
       campaignState = await program.account.campaign.fetch(campaignPDA);
       console.log("Campaign status: ", campaignState.status);
-      assert.equal(campaignState.status, 2);
+      // Frozen
+      assert.equal(campaignState.status, 5);
+    }
+  });
+
+  it("Charges donation fees to the treasury", async () => {
+    let [treasuryPDA, _treasuryBump] = await getTreasuryPDA(program, configPDA, nativeMintAddress);
+    let configState = await program.account.config.fetch(configPDA);
+    const params = {
+      roundVotingPeriodInDays: configState.roundVotingPeriodInDays,
+      minimumRequiredVotePercentage: configState.minimumRequiredVotePercentage,
+      minimumModerationVotePercentage: configState.minimumModerationVotePercentage,
+      donatorVotingRights: configState.donatorVotingRights,
+      stakerVotingRights: configState.stakerVotingRights,
+      stakerModerationRights: configState.stakerModerationRights,
+      donationFee: configState.donationFee,
+      unstakeCooldownInSeconds: configState.unstakeCooldownInSeconds,
+      moderationSlashPercentage: configState.moderationSlashPercentage,
+    };
+
+    // 5% fee
+    await program.methods
+      .updateConfig({ ...params, donationFee: new anchor.BN(500) })
+      .accounts({ config: configPDA, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+    let user = anchor.web3.Keypair.generate();
+    await airdrop(provider.connection, user, 2);
+    let [creatorProfilePDA, _creatorProfileBump] = await getCreatorProfilePDA(program, user.publicKey);
+    let [campaignPDA, _campaignBump] = await getCampaignPDA(program, user.publicKey);
+    let [vaultPDA, _vaultBump] = await getVaultPDA(program, campaignPDA);
+    let [round1PDA, _roundBump] = await getRoundPDA(program, campaignPDA, 1);
+
+    await program.methods
+      .startCampaign("Build a well", new anchor.BN(1000), 1, new anchor.BN(1000), "QmFeeTestCid", null, { flexible: {} }, false, false)
+      .accounts({
+        fundstarter: user.publicKey,
+        creatorProfile: creatorProfilePDA,
+        campaign: campaignPDA,
+        vault: vaultPDA,
+        round: round1PDA,
+        config: configPDA,
+        treasury: nativeTreasuryPDA,
+        tokenMint: nativeMintAddress,
+      })
+      .signers([user])
+      .rpc();
+
+    let donator = anchor.web3.Keypair.generate();
+    await airdrop(provider.connection, donator, 1);
+    let donatorWallet = await createAssociatedTokenAccount(program, donator, nativeMintAddress);
+    await mintTokensToWallet(donatorWallet, 200, donator, nativeMintAddress, nativeMintAuthority, program);
+    let [donatorAccountPDA, _donatorBump] = await getDonatorAccountPDA(program, round1PDA, donator.publicKey);
+
+    let initialTreasuryBalance = (await provider.connection.getTokenAccountBalance(treasuryPDA)).value.uiAmount;
+
+    await program.methods
+      .donate(new anchor.BN(200))
+      .accounts({
+        config: configPDA,
+        campaign: campaignPDA,
+        vault: vaultPDA,
+        treasury: treasuryPDA,
+        round: round1PDA,
+        donatorAccount: donatorAccountPDA,
+        donator: donator.publicKey,
+        donatorTokenAccount: donatorWallet
+      })
+      .signers([donator])
+      .rpc();
+
+    // 10 of the 200 tokens go to the treasury, the rest to the campaign
+    let treasuryBalance = (await provider.connection.getTokenAccountBalance(treasuryPDA)).value.uiAmount;
+    let vaultBalance = (await provider.connection.getTokenAccountBalance(vaultPDA)).value.uiAmount;
+    assert.equal(treasuryBalance, initialTreasuryBalance + 10);
+    assert.equal(vaultBalance, 190);
+
+    let campaignState = await program.account.campaign.fetch(campaignPDA);
+    let roundState = await program.account.round.fetch(round1PDA);
+    let donatorState = await program.account.donator.fetch(donatorAccountPDA);
+    assert.equal(campaignState.balance.toNumber(), 190);
+    assert.equal(roundState.balance.toNumber(), 190);
+    assert.equal(donatorState.amount.toNumber(), 190);
+
+    // Only the admin can withdraw from the treasury
+    let adminWallet = await createAssociatedTokenAccount(program, admin, nativeMintAddress);
+    try {
+      await program.methods
+        .withdrawTreasury(new anchor.BN(10))
+        .accounts({
+          config: configPDA,
+          admin: user.publicKey,
+          treasury: treasuryPDA,
+          destination: adminWallet,
+        })
+        .signers([user])
+        .rpc();
+      chai.assert(false, "Should fail because signer isn't the admin");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("Unauthorized");
+    }
+
+    await program.methods
+      .withdrawTreasury(new anchor.BN(10))
+      .accounts({
+        config: configPDA,
+        admin: admin.publicKey,
+        treasury: treasuryPDA,
+        destination: adminWallet,
+      })
+      .signers([admin])
+      .rpc();
+
+    let adminBalance = (await provider.connection.getTokenAccountBalance(adminWallet)).value.uiAmount;
+    treasuryBalance = (await provider.connection.getTokenAccountBalance(treasuryPDA)).value.uiAmount;
+    assert.equal(adminBalance, 10);
+    assert.equal(treasuryBalance, initialTreasuryBalance);
+
+    await program.methods
+      .updateConfig(params)
+      .accounts({ config: configPDA, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+  });
+
+  it("Uses the configured moderation quorum", async () => {
+    let [stakingPoolPDA, _poolBump] = await getStakingPoolPDA(program, configPDA);
+    let configState = await program.account.config.fetch(configPDA);
+    const params = {
+      roundVotingPeriodInDays: configState.roundVotingPeriodInDays,
+      minimumRequiredVotePercentage: configState.minimumRequiredVotePercentage,
+      minimumModerationVotePercentage: configState.minimumModerationVotePercentage,
+      donatorVotingRights: configState.donatorVotingRights,
+      stakerVotingRights: configState.stakerVotingRights,
+      stakerModerationRights: configState.stakerModerationRights,
+      donationFee: configState.donationFee,
+      unstakeCooldownInSeconds: configState.unstakeCooldownInSeconds,
+      moderationSlashPercentage: configState.moderationSlashPercentage,
+    };
+
+    async function setModerationQuorum(percentage: number) {
+      await program.methods
+        .updateConfig({ ...params, minimumModerationVotePercentage: percentage })
+        .accounts({ config: configPDA, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+    }
+
+    // Three fresh moderators, each staking the same amount
+    let moderators: [anchor.web3.Keypair, anchor.web3.PublicKey][] = [];
+    for (let i = 0; i < 3; i++) {
+      let staker = anchor.web3.Keypair.generate();
+      await airdrop(provider.connection, staker, 1);
+      let stakerWallet = await createAssociatedTokenAccount(program, staker, nativeMintAddress);
+      await mintTokensToWallet(stakerWallet, 100, staker, nativeMintAddress, nativeMintAuthority, program);
+      let [stakeAccount, _] = await getStakeAccountPDA(program, staker.publicKey);
+      await program.methods
+        .stake(new anchor.BN(100), new anchor.BN(0))
+        .accounts({
+          config: configPDA,
+          stakeAccount: stakeAccount,
+          stakerTokenAccount: stakerWallet,
+          stakingPool: stakingPoolPDA,
+          staker: staker.publicKey,
+          mint: nativeMintAddress
+        })
+        .signers([staker])
+        .rpc();
+      moderators.push([staker, stakeAccount]);
+    }
+
+    async function flagCampaign(): Promise<boolean[]> {
+      let user = anchor.web3.Keypair.generate();
+      await airdrop(provider.connection, user, 2);
+      let [creatorProfilePDA, _creatorProfileBump] = await getCreatorProfilePDA(program, user.publicKey);
+      let [campaignPDA, _campaignBump] = await getCampaignPDA(program, user.publicKey);
+      let [vaultPDA, _vaultBump] = await getVaultPDA(program, campaignPDA);
+      let [round1PDA, _roundBump] = await getRoundPDA(program, campaignPDA, 1);
+
+      await program.methods
+        .startCampaign("Totally legit", new anchor.BN(100), 1, new anchor.BN(100), "QmQuorumTestCid", null, { flexible: {} }, false, false)
+        .accounts({
+          fundstarter: user.publicKey,
+          creatorProfile: creatorProfilePDA,
+          campaign: campaignPDA,
+          vault: vaultPDA,
+          round: round1PDA,
+          config: configPDA,
+          treasury: nativeTreasuryPDA,
+          tokenMint: nativeMintAddress,
+        })
+        .signers([user])
+        .rpc();
+
+      // Validity of the campaign after each thumbs down
+      let validity: boolean[] = [];
+      for (let [moderator, stakeAccount] of moderators) {
+        let [modAccountPDA, _modBump] = await getModeratorAccountPDA(program, campaignPDA, moderator.publicKey);
+        await program.methods
+          .initStakerModeration()
+          .accounts({
+            config: configPDA,
+            campaign: campaignPDA,
+            moderatorAccount: modAccountPDA,
+            staker: moderator.publicKey,
+            stakeAccount: stakeAccount,
+          })
+          .signers([moderator])
+          .rpc();
+        await program.methods
+          .moderate(false)
+          .accounts({
+            config: configPDA,
+            campaign: campaignPDA,
+            moderatorAccount: modAccountPDA,
+            moderator: moderator.publicKey
+          })
+          .signers([moderator])
+          .rpc();
+        validity.push((await program.account.campaign.fetch(campaignPDA)).isValidCampaign);
+      }
+      return validity;
+    }
+
+    // Pick quorums that sit right at the boundary for our three moderators
+    let activeStakers = (await program.account.config.fetch(configPDA)).activeStakers.toNumber();
+
+    // More than `percentage`% of stakers must vote, three votes clear it but two don't
+    await setModerationQuorum(Math.ceil(200 / activeStakers));
+    assert.deepEqual(await flagCampaign(), [true, true, false]);
+
+    // Just above that, three votes are no longer enough
+    await setModerationQuorum(Math.ceil(300 / activeStakers));
+    assert.deepEqual(await flagCampaign(), [true, true, true]);
+
+    await program.methods
+      .updateConfig(params)
+      .accounts({ config: configPDA, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+  });
+
+  it("Lets a fundstarter run several campaigns", async () => {
+    let user = anchor.web3.Keypair.generate();
+    await airdrop(provider.connection, user, 2);
+    let [creatorProfilePDA, creatorProfileBump] = await getCreatorProfilePDA(program, user.publicKey);
+
+    for (let index = 0; index < 3; index++) {
+      let [campaignPDA, _campaignBump] = await getCampaignPDA(program, user.publicKey, index);
+      let [vaultPDA, _vaultBump] = await getVaultPDA(program, campaignPDA);
+      let [round1PDA, _roundBump] = await getRoundPDA(program, campaignPDA, 1);
+
+      await program.methods
+        .startCampaign(`Campaign #${index}`, new anchor.BN(100), 1, new anchor.BN(100), "QmManyCampaignsCid", null, { flexible: {} }, false, false)
+        .accounts({
+          fundstarter: user.publicKey,
+          creatorProfile: creatorProfilePDA,
+          campaign: campaignPDA,
+          vault: vaultPDA,
+          round: round1PDA,
+          config: configPDA,
+          treasury: nativeTreasuryPDA,
+          tokenMint: nativeMintAddress,
+        })
+        .signers([user])
+        .rpc();
+
+      let campaignState = await program.account.campaign.fetch(campaignPDA);
+      assert.equal(campaignState.index.toNumber(), index);
+      assert.ok(campaignState.fundstarter.equals(user.publicKey));
+      assert.equal(campaignState.description.toString(), `Campaign #${index}`);
+
+      let creatorProfileState = await program.account.creatorProfile.fetch(creatorProfilePDA);
+      assert.equal(creatorProfileState.campaignCount.toNumber(), index + 1);
+      assert.equal(creatorProfileState.bump, creatorProfileBump);
+    }
+
+    // Campaign seeds must match the profile's next index
+    let [stalePDA, _staleBump] = await getCampaignPDA(program, user.publicKey, 0);
+    let [staleVaultPDA, _staleVaultBump] = await getVaultPDA(program, stalePDA);
+    let [staleRoundPDA, _staleRoundBump] = await getRoundPDA(program, stalePDA, 1);
+    try {
+      await program.methods
+        .startCampaign("Reusing an index", new anchor.BN(100), 1, new anchor.BN(100), "QmManyCampaignsCid", null, { flexible: {} }, false, false)
+        .accounts({
+          fundstarter: user.publicKey,
+          creatorProfile: creatorProfilePDA,
+          campaign: stalePDA,
+          vault: staleVaultPDA,
+          round: staleRoundPDA,
+          config: configPDA,
+          treasury: nativeTreasuryPDA,
+          tokenMint: nativeMintAddress,
+        })
+        .signers([user])
+        .rpc();
+      chai.assert(false, "Should fail because campaign index 0 is taken");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("ConstraintSeeds");
+    }
+  });
+
+  it("Accumulates repeat donations from the same wallet", async () => {
+    let [treasuryPDA, _treasuryBump] = await getTreasuryPDA(program, configPDA, nativeMintAddress);
+    let user = anchor.web3.Keypair.generate();
+    await airdrop(provider.connection, user, 2);
+    let [creatorProfilePDA, _creatorProfileBump] = await getCreatorProfilePDA(program, user.publicKey);
+    let [campaignPDA, _campaignBump] = await getCampaignPDA(program, user.publicKey);
+    let [vaultPDA, _vaultBump] = await getVaultPDA(program, campaignPDA);
+    let [round1PDA, _roundBump] = await getRoundPDA(program, campaignPDA, 1);
+
+    await program.methods
+      .startCampaign("Repair the school roof", new anchor.BN(1000), 1, new anchor.BN(1000), "QmRepeatDonationCid", null, { flexible: {} }, false, false)
+      .accounts({
+        fundstarter: user.publicKey,
+        creatorProfile: creatorProfilePDA,
+        campaign: campaignPDA,
+        vault: vaultPDA,
+        round: round1PDA,
+        config: configPDA,
+        treasury: nativeTreasuryPDA,
+        tokenMint: nativeMintAddress,
+      })
+      .signers([user])
+      .rpc();
+
+    async function newDonator(): Promise<[anchor.web3.Keypair, anchor.web3.PublicKey, anchor.web3.PublicKey]> {
+      let donator = anchor.web3.Keypair.generate();
+      await airdrop(provider.connection, donator, 1);
+      let donatorWallet = await createAssociatedTokenAccount(program, donator, nativeMintAddress);
+      await mintTokensToWallet(donatorWallet, 100, donator, nativeMintAddress, nativeMintAuthority, program);
+      let [donatorAccountPDA, _] = await getDonatorAccountPDA(program, round1PDA, donator.publicKey);
+      return [donator, donatorWallet, donatorAccountPDA];
+    }
+
+    async function donate(amount: number, donator: anchor.web3.Keypair, donatorWallet: anchor.web3.PublicKey,
+      donatorAccountPDA: anchor.web3.PublicKey) {
+      await program.methods
+        .donate(new anchor.BN(amount))
+        .accounts({
+          config: configPDA,
+          campaign: campaignPDA,
+          vault: vaultPDA,
+          treasury: treasuryPDA,
+          round: round1PDA,
+          donatorAccount: donatorAccountPDA,
+          donator: donator.publicKey,
+          donatorTokenAccount: donatorWallet
+        })
+        .signers([donator])
+        .rpc();
+    }
+
+    let [donator1, donator1Wallet, donator1Account] = await newDonator();
+    await donate(30, donator1, donator1Wallet, donator1Account);
+    await donate(20, donator1, donator1Wallet, donator1Account);
+
+    let donatorState = await program.account.donator.fetch(donator1Account);
+    assert.equal(donatorState.amount.toNumber(), 50);
+    assert.equal(donatorState.donationCount.toNumber(), 2);
+    assert.equal(donatorState.round, 1);
+
+    // The same wallet is only counted once
+    let roundState = await program.account.round.fetch(round1PDA);
+    assert.equal(roundState.balance.toNumber(), 50);
+    assert.equal(roundState.donators.toNumber(), 1);
+
+    let [donator2, donator2Wallet, donator2Account] = await newDonator();
+    await donate(15, donator2, donator2Wallet, donator2Account);
+
+    roundState = await program.account.round.fetch(round1PDA);
+    assert.equal(roundState.balance.toNumber(), 65);
+    assert.equal(roundState.donators.toNumber(), 2);
+
+    let campaignState = await program.account.campaign.fetch(campaignPDA);
+    assert.equal(campaignState.balance.toNumber(), 65);
+
+    // Empty donations would let a wallet count as a donator for free
+    try {
+      await donate(0, donator2, donator2Wallet, donator2Account);
+      chai.assert(false, "Should fail because the donation is empty");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("InvalidDonationAmount");
+    }
+  });
+
+  it("Only refunds donors of terminated rounds", async () => {
+    let accounts = await newCampaign(500, 2, 100);
+    let [donator, donatorWallet] = await fundedWallet(100);
+    let donatorAccount = await donateTo(accounts, donator, donatorWallet, 60);
+
+    // The campaign is still running, so the donation stays in the vault
+    try {
+      await program.methods
+        .claimRefund()
+        .accounts({
+          campaign: accounts.campaign,
+          round: accounts.round,
+          vault: accounts.vault,
+          donatorAccount: donatorAccount,
+          donator: donator.publicKey,
+          donatorTokenAccount: donatorWallet,
+        })
+        .signers([donator])
+        .rpc();
+      chai.assert(false, "Should fail because the campaign wasn't terminated");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("RefundsUnavailable");
+    }
+
+    let donatorState = await program.account.donator.fetch(donatorAccount);
+    assert.equal(donatorState.amount.toNumber(), 60);
+    let vaultBalance = (await provider.connection.getTokenAccountBalance(accounts.vault)).value.uiAmount;
+    assert.equal(vaultBalance, 60);
+  });
+
+  it("Refunds donors of campaigns frozen by moderation", async () => {
+    let params = await governanceParams();
+    // Any single moderator is enough to reach quorum
+    await updateGovernanceParams({ ...params, minimumModerationVotePercentage: 0 });
+
+    let [moderator, _moderatorWallet, moderatorStakeAccount] = await newStaker(100);
+    let accounts = await newCampaign(500, 1, 500);
+    let [donator1, donator1Wallet] = await fundedWallet(60);
+    let [donator2, donator2Wallet] = await fundedWallet(40);
+    let donator1Account = await donateTo(accounts, donator1, donator1Wallet, 60);
+    await donateTo(accounts, donator2, donator2Wallet, 40);
+
+    // No refunds while the campaign is in good standing
+    try {
+      await claimRefund(accounts, accounts.round, donator1, donator1Wallet);
+      chai.assert(false, "Should fail because the campaign isn't frozen");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("RefundsUnavailable");
+    }
+
+    await moderateCampaign(accounts.campaign, moderator, moderatorStakeAccount, false);
+    let campaignState = await program.account.campaign.fetch(accounts.campaign);
+    assert.equal(campaignState.isValidCampaign, false);
+    assert.equal(campaignState.status, 5);
+
+    await claimRefund(accounts, accounts.round, donator1, donator1Wallet);
+    let balance = (await provider.connection.getTokenAccountBalance(donator1Wallet)).value.uiAmount;
+    assert.equal(balance, 60);
+    assert.isNull(await program.account.donator.fetchNullable(donator1Account));
+
+    campaignState = await program.account.campaign.fetch(accounts.campaign);
+    assert.equal(campaignState.balance.toNumber(), 40);
+
+    await claimRefund(accounts, accounts.round, donator2, donator2Wallet);
+    balance = (await provider.connection.getTokenAccountBalance(donator2Wallet)).value.uiAmount;
+    assert.equal(balance, 40);
+
+    let vaultBalance = (await provider.connection.getTokenAccountBalance(accounts.vault)).value.uiAmount;
+    assert.equal(vaultBalance, 0);
+    campaignState = await program.account.campaign.fetch(accounts.campaign);
+    assert.equal(campaignState.balance.toNumber(), 0);
+
+    // Refunds can only be claimed once
+    try {
+      await claimRefund(accounts, accounts.round, donator1, donator1Wallet);
+      chai.assert(false, "Should fail because the refund was already claimed");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("AccountNotInitialized");
+    }
+
+    await updateGovernanceParams(params);
+  });
+
+  it("Keeps round funds escrowed until they are released", async () => {
+    // Multi-round campaign: round 1 needs a successful vote before paying out
+    let accounts = await newCampaign(500, 2, 100);
+    let fundstarterWallet = await createAssociatedTokenAccount(program, accounts.fundstarter, nativeMintAddress);
+    let [donator, donatorWallet] = await fundedWallet(100);
+    await donateTo(accounts, donator, donatorWallet, 100);
+
+    let roundState = await program.account.round.fetch(accounts.round);
+    assert.equal(roundState.status, 2);
+
+    try {
+      await withdrawFrom(accounts, accounts.round, fundstarterWallet);
+      chai.assert(false, "Should fail because round 1 hasn't been voted on");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("RoundNotReleased");
+    }
+    let vaultBalance = (await provider.connection.getTokenAccountBalance(accounts.vault)).value.uiAmount;
+    assert.equal(vaultBalance, 100);
+
+    // Single round campaign: released as soon as the target is met
+    accounts = await newCampaign(100, 1, 100);
+    fundstarterWallet = await createAssociatedTokenAccount(program, accounts.fundstarter, nativeMintAddress);
+    let [donator1, donator1Wallet] = await fundedWallet(50);
+    let [donator2, donator2Wallet] = await fundedWallet(50);
+    await donateTo(accounts, donator1, donator1Wallet, 50);
+
+    try {
+      await withdrawFrom(accounts, accounts.round, fundstarterWallet);
+      chai.assert(false, "Should fail because the round is still open");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("RoundNotReleased");
+    }
+
+    await donateTo(accounts, donator2, donator2Wallet, 50);
+    await withdrawFrom(accounts, accounts.round, fundstarterWallet);
+
+    let withdrawn = (await provider.connection.getTokenAccountBalance(fundstarterWallet)).value.uiAmount;
+    assert.equal(withdrawn, 100);
+    roundState = await program.account.round.fetch(accounts.round);
+    assert.equal(roundState.withdrawn.toNumber(), 100);
+    let campaignState = await program.account.campaign.fetch(accounts.campaign);
+    assert.equal(campaignState.status, 3);
+    // The vault is closed after the final payout
+    assert.isNull(await provider.connection.getAccountInfo(accounts.vault));
+  });
+
+  it("Fails rounds that miss their deadline", async () => {
+    const sleep = (ms: number) => new Promise(resolve => setTimeout(resolve, ms));
+    let now = Math.floor(Date.now() / 1000);
+
+    // Deadlines must be in the future
+    try {
+      await newCampaign(100, 1, 100, now - 10);
+      chai.assert(false, "Should fail because the deadline already passed");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("InvalidDeadline");
+    }
+
+    let accounts = await newCampaign(100, 1, 100, now + 5, { allOrNothing: {} });
+    let roundState = await program.account.round.fetch(accounts.round);
+    assert.equal(roundState.deadline.toNumber(), now + 5);
+    assert.isAbove(roundState.startTime.toNumber(), 0);
+
+    let [donator1, donator1Wallet] = await fundedWallet(40);
+    let [donator2, donator2Wallet] = await fundedWallet(40);
+    await donateTo(accounts, donator1, donator1Wallet, 40);
+
+    async function closeExpiredRound() {
+      await program.methods
+        .closeExpiredRound()
+        .accounts({ campaign: accounts.campaign, round: accounts.round })
+        .rpc();
+    }
+
+    try {
+      await closeExpiredRound();
+      chai.assert(false, "Should fail because the deadline hasn't passed");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("RoundNotExpired");
+    }
+
+    await sleep(8000);
+
+    try {
+      await donateTo(accounts, donator2, donator2Wallet, 40);
+      chai.assert(false, "Should fail because the deadline passed");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("RoundDeadlinePassed");
+    }
+
+    await closeExpiredRound();
+    roundState = await program.account.round.fetch(accounts.round);
+    assert.equal(roundState.status, 5);
+    // All-or-nothing campaign failed
+    let campaignState = await program.account.campaign.fetch(accounts.campaign);
+    assert.equal(campaignState.status, 6);
+    assert.equal(campaignState.canStartNextRound, false);
+
+    await claimRefund(accounts, accounts.round, donator1, donator1Wallet);
+    let balance = (await provider.connection.getTokenAccountBalance(donator1Wallet)).value.uiAmount;
+    assert.equal(balance, 40);
+  });
+
+  it("Lets flexible campaigns keep a partial raise", async () => {
+    const sleep = (ms: number) => new Promise(resolve => setTimeout(resolve, ms));
+    let now = Math.floor(Date.now() / 1000);
+
+    let accounts = await newCampaign(100, 1, 100, now + 5, { flexible: {} });
+    let fundstarterWallet = await createAssociatedTokenAccount(program, accounts.fundstarter, nativeMintAddress);
+    let [donator, donatorWallet] = await fundedWallet(40);
+    await donateTo(accounts, donator, donatorWallet, 40);
+
+    await sleep(8000);
+    await program.methods
+      .closeExpiredRound()
+      .accounts({ campaign: accounts.campaign, round: accounts.round })
+      .rpc();
+
+    let campaignState = await program.account.campaign.fetch(accounts.campaign);
+    assert.equal(campaignState.status, 7);
+
+    // Donors of a flexible campaign aren't refunded
+    try {
+      await claimRefund(accounts, accounts.round, donator, donatorWallet);
+      chai.assert(false, "Should fail because flexible campaigns keep partial raises");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("RefundsUnavailable");
+    }
+
+    await withdrawFrom(accounts, accounts.round, fundstarterWallet);
+    let withdrawn = (await provider.connection.getTokenAccountBalance(fundstarterWallet)).value.uiAmount;
+    assert.equal(withdrawn, 40);
+    campaignState = await program.account.campaign.fetch(accounts.campaign);
+    assert.equal(campaignState.status, 7);
+  });
+
+  it("Pays out all-or-nothing campaigns that meet their target", async () => {
+    // A single round's target is the whole campaign target
+    let accounts = await newCampaign(100, 1, 100, null, { allOrNothing: {} });
+    let fundstarterWallet = await createAssociatedTokenAccount(program, accounts.fundstarter, nativeMintAddress);
+    let [donator, donatorWallet] = await fundedWallet(100);
+    await donateTo(accounts, donator, donatorWallet, 100);
+
+    await withdrawFrom(accounts, accounts.round, fundstarterWallet);
+    let withdrawn = (await provider.connection.getTokenAccountBalance(fundstarterWallet)).value.uiAmount;
+    assert.equal(withdrawn, 100);
+    let campaignState = await program.account.campaign.fetch(accounts.campaign);
+    assert.equal(campaignState.fundingMode, 1);
+    assert.equal(campaignState.status, 3);
+  });
+
+  it("Snapshots staking totals when voting opens", async () => {
+    const sleep = (ms: number) => new Promise(resolve => setTimeout(resolve, ms));
+    let accounts = await newCampaign(500, 2, 100);
+    let [donator, donatorWallet] = await fundedWallet(100);
+    await donateTo(accounts, donator, donatorWallet, 100);
+    let [staker, _stakerWallet, stakeAccount] = await newStaker(100);
+
+    let roundVotes = await openVoting(accounts);
+    let configState = await program.account.config.fetch(configPDA);
+    let roundVotesState = await program.account.roundVote.fetch(roundVotes);
+    let snapshotStake = configState.totalAmountStaked.toNumber();
+    assert.equal(roundVotesState.totalAmountStaked.toNumber(), snapshotStake);
+    assert.equal(roundVotesState.activeStakers.toNumber(), configState.activeStakers.toNumber());
+
+    // Staking after voting opened doesn't move the snapshot
+    await sleep(2000);
+    let [lateStaker, _lateStakerWallet, lateStakeAccount] = await newStaker(1000);
+    roundVotesState = await program.account.roundVote.fetch(roundVotes);
+    assert.equal(roundVotesState.totalAmountStaked.toNumber(), snapshotStake);
+
+    let voterAccount = await initStakerVoting(accounts, roundVotes, staker, stakeAccount);
+    let voterState = await program.account.nextRoundVoter.fetch(voterAccount);
+    assert.equal(
+      voterState.votingPower.toNumber(),
+      stakerPower(100, snapshotStake, configState.stakerVotingRights)
+    );
+
+    try {
+      await initStakerVoting(accounts, roundVotes, lateStaker, lateStakeAccount);
+      chai.assert(false, "Should fail because the stake was deposited after voting opened");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("StakedAfterSnapshot");
+    }
+  });
+
+  it("Locks stake while it backs a moderation", async () => {
+    let [staker, stakerWallet, stakeAccount] = await newStaker(100);
+    let accounts = await newCampaign(100, 1, 100);
+    await moderateCampaign(accounts.campaign, staker, stakeAccount, true);
+
+    let stakeAccountState = await program.account.stakeAccount.fetch(stakeAccount);
+    assert.equal(stakeAccountState.activeModerations.toNumber(), 1);
+
+    try {
+      await requestUnstake(staker, stakeAccount, 100);
+      chai.assert(false, "Should fail because the stake backs an ongoing moderation");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("StakeLocked");
+    }
+
+    let [modAccountPDA, _modBump] = await getModeratorAccountPDA(program, accounts.campaign, staker.publicKey);
+    try {
+      await releaseModeration(accounts.campaign, staker.publicKey);
+      chai.assert(false, "Should fail because the campaign is still running");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("ModerationStillActive");
+    }
+
+    // Conclude the campaign, then the moderation no longer holds the stake
+    let fundstarterWallet = await createAssociatedTokenAccount(program, accounts.fundstarter, nativeMintAddress);
+    let [donator, donatorWallet] = await fundedWallet(100);
+    await donateTo(accounts, donator, donatorWallet, 100);
+    await withdrawFrom(accounts, accounts.round, fundstarterWallet);
+
+    await releaseModeration(accounts.campaign, staker.publicKey);
+    stakeAccountState = await program.account.stakeAccount.fetch(stakeAccount);
+    assert.equal(stakeAccountState.activeModerations.toNumber(), 0);
+    assert.isNull(await provider.connection.getAccountInfo(modAccountPDA));
+    await requestUnstake(staker, stakeAccount, 100);
+  });
+
+  it("Unstakes part or all of a deposit after a cooldown", async () => {
+    let [staker, stakerWallet, stakeAccount] = await newStaker(100);
+    let configState = await program.account.config.fetch(configPDA);
+    let activeStakers = configState.activeStakers.toNumber();
+    let totalStaked = configState.totalAmountStaked.toNumber();
+
+    // Requested tokens stop counting as stake right away
+    await requestUnstake(staker, stakeAccount, 40);
+    configState = await program.account.config.fetch(configPDA);
+    assert.equal(configState.activeStakers.toNumber(), activeStakers);
+    assert.equal(configState.totalAmountStaked.toNumber(), totalStaked - 40);
+    let stakeAccountState = await program.account.stakeAccount.fetch(stakeAccount);
+    assert.equal(stakeAccountState.deposit.toNumber(), 60);
+    assert.equal(stakeAccountState.pendingUnstake.toNumber(), 40);
+
+    try {
+      await completeUnstake(staker, stakerWallet, stakeAccount);
+      chai.assert(false, "Should fail because the cooldown hasn't ended");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("UnstakeCooldownActive");
+    }
+
+    try {
+      await requestUnstake(staker, stakeAccount, 61);
+      chai.assert(false, "Should fail because only 60 tokens are left staked");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("InvalidStakeAmount");
+    }
+
+    // Without a cooldown the rest can be withdrawn right away, closing the stake account
+    let params = await governanceParams();
+    await updateGovernanceParams({ ...params, unstakeCooldownInSeconds: new anchor.BN(0) });
+    await requestUnstake(staker, stakeAccount, 60);
+    await updateGovernanceParams(params);
+
+    configState = await program.account.config.fetch(configPDA);
+    assert.equal(configState.activeStakers.toNumber(), activeStakers - 1);
+    assert.equal(configState.totalAmountStaked.toNumber(), totalStaked - 100);
+
+    await completeUnstake(staker, stakerWallet, stakeAccount);
+    assert.isNull(await provider.connection.getAccountInfo(stakeAccount));
+    let walletBalance = (await provider.connection.getTokenAccountBalance(stakerWallet)).value.uiAmount;
+    assert.equal(walletBalance, 100);
+  });
+
+  it("Adds to an existing stake", async () => {
+    let [stakingPoolPDA, _poolBump] = await getStakingPoolPDA(program, configPDA);
+    let [staker, stakerWallet, stakeAccount] = await newStaker(100);
+    await mintTokensToWallet(stakerWallet, 300, staker, nativeMintAddress, nativeMintAuthority, program);
+    let configState = await program.account.config.fetch(configPDA);
+    let activeStakers = configState.activeStakers.toNumber();
+    let totalStaked = configState.totalAmountStaked.toNumber();
+    let stakeAccountState = await program.account.stakeAccount.fetch(stakeAccount);
+    let stakeTime = stakeAccountState.stakeTime.toNumber();
+
+    await program.methods
+      .increaseStake(new anchor.BN(300), new anchor.BN(0))
+      .accounts({
+        config: configPDA,
+        stakeAccount: stakeAccount,
+        stakerTokenAccount: stakerWallet,
+        stakingPool: stakingPoolPDA,
+        staker: staker.publicKey,
+        mint: nativeMintAddress
+      })
+      .signers([staker])
+      .rpc();
+
+    configState = await program.account.config.fetch(configPDA);
+    assert.equal(configState.activeStakers.toNumber(), activeStakers);
+    assert.equal(configState.totalAmountStaked.toNumber(), totalStaked + 300);
+
+    // Stake age is averaged over the deposits
+    stakeAccountState = await program.account.stakeAccount.fetch(stakeAccount);
+    let lastDepositTime = stakeAccountState.lastDepositTime.toNumber();
+    assert.equal(stakeAccountState.deposit.toNumber(), 400);
+    assert.isAtLeast(lastDepositTime, stakeTime);
+    assert.equal(
+      stakeAccountState.stakeTime.toNumber(),
+      Math.floor((stakeTime * 100 + lastDepositTime * 300) / 400)
+    );
+
+    // An unlocked staker can opt in to the lock boost without adding tokens
+    const lockFor = (amount: number, lockDuration: number) => program.methods
+      .increaseStake(new anchor.BN(amount), new anchor.BN(lockDuration))
+      .accounts({
+        config: configPDA,
+        stakeAccount: stakeAccount,
+        stakerTokenAccount: stakerWallet,
+        stakingPool: stakingPoolPDA,
+        staker: staker.publicKey,
+        mint: nativeMintAddress
+      })
+      .signers([staker])
+      .rpc();
+    await lockFor(0, 24 * 60 * 60);
+    stakeAccountState = await program.account.stakeAccount.fetch(stakeAccount);
+    let lockEnd = stakeAccountState.lockEnd.toNumber();
+    assert.isAtLeast(lockEnd, lastDepositTime + 24 * 60 * 60);
+    assert.equal(stakeAccountState.deposit.toNumber(), 400);
+
+    // Shorter locks leave the current one in place
+    await lockFor(0, 60);
+    stakeAccountState = await program.account.stakeAccount.fetch(stakeAccount);
+    assert.equal(stakeAccountState.lockEnd.toNumber(), lockEnd);
+
+    try {
+      await lockFor(0, 0);
+      chai.assert(false, "Should fail because nothing is added or locked");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("InvalidStakeAmount");
+    }
+  });
+
+  it("Pays staking rewards out of donation fees", async () => {
+    let [stakingPoolPDA, _poolBump] = await getStakingPoolPDA(program, configPDA);
+    let [treasuryPDA, _treasuryBump] = await getTreasuryPDA(program, configPDA, nativeMintAddress);
+    let [staker, stakerWallet, stakeAccount] = await newStaker(1000);
+
+    // Collect 1000 tokens of fees at a 50% donation fee
+    let params = await governanceParams();
+    await updateGovernanceParams({ ...params, donationFee: new anchor.BN(5000) });
+    let accounts = await newCampaign(5000, 1, 5000);
+    let [donator, donatorWallet] = await fundedWallet(2000);
+    await donateTo(accounts, donator, donatorWallet, 2000);
+    await updateGovernanceParams(params);
+
+    const fundRewards = (signer: anchor.web3.Keypair) => program.methods
+      .fundRewards(new anchor.BN(1000))
+      .accounts({
+        config: configPDA,
+        admin: signer.publicKey,
+        treasury: treasuryPDA,
+        stakingPool: stakingPoolPDA,
+      })
+      .signers([signer])
+      .rpc();
+
+    try {
+      await fundRewards(staker);
+      chai.assert(false, "Should fail because signer isn't the admin");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("Unauthorized");
+    }
+
+    let rewardPerToken = (await program.account.config.fetch(configPDA)).rewardPerToken;
+    await fundRewards(admin);
+    let updatedRewardPerToken = (await program.account.config.fetch(configPDA)).rewardPerToken;
+    let expectedReward = updatedRewardPerToken.sub(rewardPerToken)
+      .mul(new anchor.BN(1000))
+      .div(new anchor.BN("1000000000000"))
+      .toNumber();
+    assert.isAbove(expectedReward, 0);
+
+    const claimRewards = () => program.methods
+      .claimRewards()
+      .accounts({
+        config: configPDA,
+        staker: staker.publicKey,
+        stakeAccount: stakeAccount,
+        stakingPool: stakingPoolPDA,
+        stakerTokenAccount: stakerWallet,
+      })
+      .signers([staker])
+      .rpc();
+
+    await claimRewards();
+    let walletBalance = (await provider.connection.getTokenAccountBalance(stakerWallet)).value.uiAmount;
+    assert.equal(walletBalance, expectedReward);
+
+    try {
+      await claimRewards();
+      chai.assert(false, "Should fail because the rewards were already claimed");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("NoRewardsToClaim");
+    }
+  });
+
+  it("Slashes moderators who voted against the outcome", async () => {
+    let [treasuryPDA, _treasuryBump] = await getTreasuryPDA(program, configPDA, nativeMintAddress);
+    let params = await governanceParams();
+    // Any single moderator is enough to reach quorum, losing moderators forfeit 10%
+    await updateGovernanceParams({ ...params, minimumModerationVotePercentage: 0, moderationSlashPercentage: 10 });
+
+    let [honest, _honestWallet, honestStakeAccount] = await newStaker(300);
+    let [dishonest, _dishonestWallet, dishonestStakeAccount] = await newStaker(100);
+    let accounts = await newCampaign(500, 1, 500);
+
+    await moderateCampaign(accounts.campaign, dishonest, dishonestStakeAccount, true);
+    await moderateCampaign(accounts.campaign, honest, honestStakeAccount, false);
+    let campaignState = await program.account.campaign.fetch(accounts.campaign);
+    assert.equal(campaignState.status, 5);
+
+    // The outcome is final, late moderators can't change who gets slashed
+    let [late, _lateWallet, lateStakeAccount] = await newStaker(100);
+    try {
+      await moderateCampaign(accounts.campaign, late, lateStakeAccount, true);
+      chai.assert(false, "Should fail because the campaign has concluded");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("ModerationClosed");
+    }
+
+    let treasuryBalance = (await provider.connection.getTokenAccountBalance(treasuryPDA)).value.uiAmount;
+    let totalStaked = (await program.account.config.fetch(configPDA)).totalAmountStaked.toNumber();
+
+    await releaseModeration(accounts.campaign, dishonest.publicKey);
+    await releaseModeration(accounts.campaign, honest.publicKey);
+
+    let dishonestState = await program.account.stakeAccount.fetch(dishonestStakeAccount);
+    let honestState = await program.account.stakeAccount.fetch(honestStakeAccount);
+    assert.equal(dishonestState.deposit.toNumber(), 90);
+    assert.equal(dishonestState.activeModerations.toNumber(), 0);
+    assert.equal(honestState.deposit.toNumber(), 300);
+    assert.equal(honestState.activeModerations.toNumber(), 0);
+
+    let updatedTreasuryBalance = (await provider.connection.getTokenAccountBalance(treasuryPDA)).value.uiAmount;
+    assert.equal(updatedTreasuryBalance, treasuryBalance + 10);
+    let configState = await program.account.config.fetch(configPDA);
+    assert.equal(configState.totalAmountStaked.toNumber(), totalStaked - 10);
+
+    await updateGovernanceParams(params);
+  });
+
+  it("Boosts the voting power of locked stake", async () => {
+    const YEAR = 365 * 24 * 60 * 60;
+    try {
+      await newStaker(100, YEAR + 1);
+      chai.assert(false, "Should fail because the lock is longer than a year");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("InvalidLockDuration");
+    }
+
+    let [locked, _lockedWallet, lockedStakeAccount] = await newStaker(100, YEAR);
+    let [unlocked, _unlockedWallet, unlockedStakeAccount] = await newStaker(100);
+
+    let stakeAccountState = await program.account.stakeAccount.fetch(lockedStakeAccount);
+    assert.equal(stakeAccountState.lockEnd.toNumber(), stakeAccountState.stakeTime.toNumber() + YEAR);
+    try {
+      await requestUnstake(locked, lockedStakeAccount, 100);
+      chai.assert(false, "Should fail because the stake is locked for a year");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("StakeLocked");
+    }
+
+    let accounts = await newCampaign(100, 1, 100);
+    await moderateCampaign(accounts.campaign, locked, lockedStakeAccount, true);
+    await moderateCampaign(accounts.campaign, unlocked, unlockedStakeAccount, true);
+
+    let configState = await program.account.config.fetch(configPDA);
+    let totalStake = configState.totalAmountStaked.toNumber();
+    let rights = configState.stakerModerationRights;
+    let [lockedModerator, _lockedBump] = await getModeratorAccountPDA(program, accounts.campaign, locked.publicKey);
+    let [unlockedModerator, _unlockedBump] = await getModeratorAccountPDA(program, accounts.campaign, unlocked.publicKey);
+    let lockedPower = (await program.account.moderator.fetch(lockedModerator)).votingPower.toNumber();
+    let unlockedPower = (await program.account.moderator.fetch(unlockedModerator)).votingPower.toNumber();
+
+    // A year-long lock doubles the weight, less the few seconds it has already decayed
+    assert.equal(unlockedPower, stakerPower(100, totalStake, rights));
+    assert.isAtMost(lockedPower, stakerPower(100, totalStake, rights, 20_000));
+    assert.isAtLeast(lockedPower, stakerPower(100, totalStake, rights, 19_999));
+  });
+
+  it("Delegates staking power to another staker", async () => {
+    let [delegator, _delegatorWallet, delegatorStakeAccount] = await newStaker(100);
+    let [delegate, _delegateWallet, delegateStakeAccount] = await newStaker(300);
+    let [delegationPDA, _delegationBump] = await getDelegationPDA(program, delegator.publicKey);
+
+    await delegateStake(delegator, delegate.publicKey);
+    let delegationState = await program.account.delegation.fetch(delegationPDA);
+    assert.ok(delegationState.delegate.equals(delegate.publicKey));
+    assert.equal(delegationState.amount.toNumber(), 100);
+    assert.isAbove(delegationState.delegatedAt.toNumber(), 0);
+    assert.equal((await program.account.stakeAccount.fetch(delegatorStakeAccount)).delegated, true);
+    assert.equal((await program.account.stakeAccount.fetch(delegateStakeAccount)).delegatedStake.toNumber(), 100);
+
+    // The delegator's stake can't be used or withdrawn while it is lent out
+    let accounts = await newCampaign(100, 1, 100);
+    try {
+      await moderateCampaign(accounts.campaign, delegator, delegatorStakeAccount, true);
+      chai.assert(false, "Should fail because the stake is delegated");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("StakeDelegated");
+    }
+    try {
+      await requestUnstake(delegator, delegatorStakeAccount, 100);
+      chai.assert(false, "Should fail because the stake is delegated");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("StakeDelegated");
+    }
+
+    await moderateCampaign(accounts.campaign, delegate, delegateStakeAccount, true);
+    let configState = await program.account.config.fetch(configPDA);
+    let [modAccountPDA, _modBump] = await getModeratorAccountPDA(program, accounts.campaign, delegate.publicKey);
+    let moderatorState = await program.account.moderator.fetch(modAccountPDA);
+    assert.equal(
+      moderatorState.votingPower.toNumber(),
+      stakerPower(400, configState.totalAmountStaked.toNumber(), configState.stakerModerationRights)
+    );
+
+    // Revoking doesn't wait for the delegate's votes, but the stake can't back them a second time
+    // and stays locked until the delegate's moderation closes
+    await revokeDelegation(delegator, delegate.publicKey);
+    assert.isNull(await provider.connection.getAccountInfo(delegationPDA));
+    assert.equal((await program.account.stakeAccount.fetch(delegateStakeAccount)).delegatedStake.toNumber(), 0);
+    try {
+      await moderateCampaign(accounts.campaign, delegator, delegatorStakeAccount, false);
+      chai.assert(false, "Should fail because the stake was delegated when the campaign started");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("DelegatedWhenOpened");
+    }
+    try {
+      await requestUnstake(delegator, delegatorStakeAccount, 100);
+      chai.assert(false, "Should fail because the delegate's moderation is still open");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("StakeLocked");
+    }
+  });
+
+  it("Counts stake delegated before a round vote opened", async () => {
+    let accounts = await newCampaign(500, 2, 100);
+    let [donator, donatorWallet] = await fundedWallet(100);
+    await donateTo(accounts, donator, donatorWallet, 100);
+    let [delegator, _delegatorWallet, _delegatorStakeAccount] = await newStaker(100);
+    let [delegate, _delegateWallet, delegateStakeAccount] = await newStaker(300);
+    let [delegationPDA, _delegationBump] = await getDelegationPDA(program, delegator.publicKey);
+    await delegateStake(delegator, delegate.publicKey);
+
+    let roundVotes = await openVoting(accounts);
+    let configState = await program.account.config.fetch(configPDA);
+    let voterAccount = await initStakerVoting(accounts, roundVotes, delegate, delegateStakeAccount, [delegationPDA]);
+    let voterState = await program.account.nextRoundVoter.fetch(voterAccount);
+    assert.equal(
+      voterState.votingPower.toNumber(),
+      stakerPower(400, configState.totalAmountStaked.toNumber(), configState.stakerVotingRights)
+    );
+  });
+
+  it("Lets voters change their ballot while voting is open", async () => {
+    let accounts = await newCampaign(500, 2, 100);
+    let [donator, donatorWallet] = await fundedWallet(100);
+    await donateTo(accounts, donator, donatorWallet, 100);
+    let [staker, _stakerWallet, stakeAccount] = await newStaker(100);
+
+    let roundVotes = await openVoting(accounts);
+    let voterAccount = await initStakerVoting(accounts, roundVotes, staker, stakeAccount);
+    let votingPower = (await program.account.nextRoundVoter.fetch(voterAccount)).votingPower.toNumber();
+
+    await castVote(accounts, roundVotes, staker, true);
+    let roundVotesState = await program.account.roundVote.fetch(roundVotes);
+    assert.equal(roundVotesState.continueCampaign.toNumber(), votingPower);
+    assert.equal(roundVotesState.terminateCampaign.toNumber(), 0);
+
+    await castVote(accounts, roundVotes, staker, false);
+    roundVotesState = await program.account.roundVote.fetch(roundVotes);
+    assert.equal(roundVotesState.continueCampaign.toNumber(), 0);
+    assert.equal(roundVotesState.terminateCampaign.toNumber(), votingPower);
+    assert.equal(roundVotesState.stakersVoted.toNumber(), 1);
+
+    let voterState = await program.account.nextRoundVoter.fetch(voterAccount);
+    assert.equal(voterState.hasVoted, true);
+    assert.equal(voterState.choice, false);
+  });
+
+  it("Hides round votes until they are revealed in commit-reveal campaigns", async () => {
+    let accounts = await newCampaign(500, 2, 100, null, { flexible: {} }, true);
+    assert.equal((await program.account.campaign.fetch(accounts.campaign)).commitReveal, true);
+    let [donator, donatorWallet] = await fundedWallet(200);
+    await donateTo(accounts, donator, donatorWallet, 100);
+    let [staker, _stakerWallet, stakeAccount] = await newStaker(100);
+
+    let roundVotes = await openVoting(accounts);
+    let configState = await program.account.config.fetch(configPDA);
+    let roundVotesState = await program.account.roundVote.fetch(roundVotes);
+    let votingPeriod = configState.roundVotingPeriodInDays * 24 * 60 * 60;
+    assert.equal(roundVotesState.revealEndTime.toNumber(), roundVotesState.endTime.toNumber() + votingPeriod);
+
+    // The stake stays locked through the reveal window
+    let voterAccount = await initStakerVoting(accounts, roundVotes, staker, stakeAccount);
+    let stakeAccountState = await program.account.stakeAccount.fetch(stakeAccount);
+    assert.equal(stakeAccountState.lockedUntil.toNumber(), roundVotesState.revealEndTime.toNumber());
+
+    try {
+      await castVote(accounts, roundVotes, staker, true);
+      chai.assert(false, "Should fail because ballots have to be committed");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("WrongVotingMode");
+    }
+
+    // Committing again replaces the commitment, nothing is tallied until reveal
+    let salt = Buffer.alloc(32, 7);
+    await commitVote(accounts, roundVotes, staker, ballotCommitment(staker.publicKey, roundVotes, true, salt));
+    await commitVote(accounts, roundVotes, staker, ballotCommitment(staker.publicKey, roundVotes, false, salt));
+    let voterState = await program.account.nextRoundVoter.fetch(voterAccount);
+    assert.deepEqual(voterState.commitment, ballotCommitment(staker.publicKey, roundVotes, false, salt));
+    assert.equal(voterState.hasVoted, false);
+    roundVotesState = await program.account.roundVote.fetch(roundVotes);
+    assert.equal(roundVotesState.terminateCampaign.toNumber(), 0);
+    assert.equal(roundVotesState.stakersVoted.toNumber(), 0);
+
+    try {
+      await program.methods
+        .revealVote(false, [...salt])
+        .accounts({
+          campaign: accounts.campaign,
+          round: accounts.round,
+          voterAccount: voterAccount,
+          voter: staker.publicKey,
+          roundVotes: roundVotes
+        })
+        .signers([staker])
+        .rpc();
+      chai.assert(false, "Should fail because voting is still open");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("OutsideRevealWindow");
+    }
+
+    // Public campaigns don't take commitments
+    let publicAccounts = await newCampaign(500, 2, 100);
+    await donateTo(publicAccounts, donator, donatorWallet, 100);
+    let publicRoundVotes = await openVoting(publicAccounts);
+    await initStakerVoting(publicAccounts, publicRoundVotes, staker, stakeAccount);
+    try {
+      await commitVote(publicAccounts, publicRoundVotes, staker, ballotCommitment(staker.publicKey, publicRoundVotes, true, salt));
+      chai.assert(false, "Should fail because the campaign votes in public");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("WrongVotingMode");
+    }
+  });
+
+  it("Weighs donators by the square root of their donation in quadratic campaigns", async () => {
+    // The same donations in both modes: a whale terminating against five small donators continuing
+    const donations = [400, 25, 25, 25, 25, 25];
+    let votingRights = (await program.account.config.fetch(configPDA)).donatorVotingRights;
+
+    async function tallyRound(quadraticVoting: boolean): Promise<[number, number]> {
+      let accounts = await newCampaign(1000, 2, 525, null, { flexible: {} }, false, quadraticVoting);
+      let donators: [anchor.web3.Keypair, anchor.web3.PublicKey][] = [];
+      for (const amount of donations) {
+        let [donator, donatorWallet] = await fundedWallet(amount);
+        donators.push([donator, await donateTo(accounts, donator, donatorWallet, amount)]);
+      }
+      let roundVotes = await openVoting(accounts);
+      let roundState = await program.account.round.fetch(accounts.round);
+
+      for (const [index, [donator, donatorAccount]] of donators.entries()) {
+        let [voterAccount, _voterBump] = await getVoterAccountPDA(program, accounts.round, donator.publicKey);
+        await program.methods
+          .initDonatorVoting()
+          .accounts({
+            config: configPDA,
+            campaign: accounts.campaign,
+            round: accounts.round,
+            donator: donator.publicKey,
+            donatorAccount: donatorAccount,
+            voterAccount: voterAccount
+          })
+          .signers([donator])
+          .rpc();
+
+        let donation = (await program.account.donator.fetch(donatorAccount)).amount.toNumber();
+        let expectedVotingPower = quadraticVoting
+          ? Math.trunc(Math.floor(Math.sqrt(donation)) * votingRights * 100 / roundState.sqrtDonations.toNumber())
+          : Math.trunc(donation * votingRights * 100 / roundState.balance.toNumber());
+        let voterState = await program.account.nextRoundVoter.fetch(voterAccount);
+        assert.equal(voterState.votingPower.toNumber(), expectedVotingPower);
+
+        await castVote(accounts, roundVotes, donator, index != 0);
+      }
+
+      let roundVotesState = await program.account.roundVote.fetch(roundVotes);
+      return [roundVotesState.continueCampaign.toNumber(), roundVotesState.terminateCampaign.toNumber()];
+    }
+
+    let [linearContinue, linearTerminate] = await tallyRound(false);
+    let [quadraticContinue, quadraticTerminate] = await tallyRound(true);
+    assert.isAbove(linearTerminate, linearContinue);
+    assert.isAbove(quadraticContinue, quadraticTerminate);
+  });
+
+  // Must stay last: no admin endpoint is reachable afterwards
+  it("Renounces admin", async () => {
+    await program.methods
+      .renounceAdmin()
+      .accounts({ config: configPDA, admin: admin.publicKey })
+      .signers([admin])
+      .rpc();
+
+    let configState = await program.account.config.fetch(configPDA);
+    assert.ok(configState.admin.equals(anchor.web3.PublicKey.default));
+    assert.ok(configState.pendingAdmin.equals(anchor.web3.PublicKey.default));
+
+    try {
+      await program.methods
+        .proposeAdmin(admin.publicKey)
+        .accounts({ config: configPDA, admin: admin.publicKey })
+        .signers([admin])
+        .rpc();
+      chai.assert(false, "Should fail because admin was renounced");
+    } catch (_err) {
+      expect(_err).to.be.instanceOf(AnchorError);
+      const err: AnchorError = _err;
+      expect(err.error.errorCode.code).to.equal("Unauthorized");
     }
   });
 
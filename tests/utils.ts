@@ -82,15 +82,30 @@ export const getConfigPDA = async(program)
     return [pda, bump];
 }
 
+// creatorProfilePDA
+export const getCreatorProfilePDA = async(program, fundstarterAddress: anchor.web3.PublicKey)
+: Promise<[anchor.web3.PublicKey, number]> => {
+    let pda: anchor.web3.PublicKey;
+    let bump: number;
+
+    [pda, bump] = await anchor.web3.PublicKey.findProgramAddress(
+        [Buffer.from(anchor.utils.bytes.utf8.encode("creator")),
+        fundstarterAddress.toBuffer()],
+        program.programId
+    );
+
+    return [pda, bump];
+}
+
 // campaignPDA
-export const getCampaignPDA = async(program, fundstarterAddress: anchor.web3.PublicKey)
+export const getCampaignPDA = async(program, fundstarterAddress: anchor.web3.PublicKey, index: number = 0)
 : Promise<[anchor.web3.PublicKey, number]> => {
     let pda: anchor.web3.PublicKey;
     let bump: number;
 
     [pda, bump] = await anchor.web3.PublicKey.findProgramAddress(
         [Buffer.from(anchor.utils.bytes.utf8.encode("campaign")),
-        fundstarterAddress.toBuffer()],
+        fundstarterAddress.toBuffer(), new anchor.BN(index).toBuffer('le', 8)],
         program.programId
     );
 
@@ -193,6 +208,22 @@ export const getStakingPoolPDA = async(program, configAddress: anchor.web3.Publi
 }
   
 
+// treasuryPDA
+export const getTreasuryPDA = async(program, configAddress: anchor.web3.PublicKey, mintAddress: anchor.web3.PublicKey)
+: Promise<[anchor.web3.PublicKey, number]> => {
+    let pda: anchor.web3.PublicKey;
+    let bump: number;
+
+    [pda, bump] = await anchor.web3.PublicKey.findProgramAddress(
+        [Buffer.from(anchor.utils.bytes.utf8.encode("treasury")),
+        configAddress.toBuffer(), mintAddress.toBuffer()],
+        program.programId
+    );
+
+    return [pda, bump];
+}
+  
+
 // stakeAccountPDA
 export const getStakeAccountPDA = async(program, stakerAddress: anchor.web3.PublicKey)
 : Promise<[anchor.web3.PublicKey, number]> => {
@@ -207,6 +238,20 @@ export const getStakeAccountPDA = async(program, stakerAddress: anchor.web3.Publ
 
     return [pda, bump];
 }
+
+export const getDelegationPDA = async(program, delegatorAddress: anchor.web3.PublicKey)
+: Promise<[anchor.web3.PublicKey, number]> => {
+    let pda: anchor.web3.PublicKey;
+    let bump: number;
+
+    [pda, bump] = await anchor.web3.PublicKey.findProgramAddress(
+        [Buffer.from(anchor.utils.bytes.utf8.encode("delegation")),
+        delegatorAddress.toBuffer()],
+        program.programId
+    );
+
+    return [pda, bump];
+}
   
 
   // voterAccountPDA